@@ -0,0 +1,320 @@
+//! The `#[messages]` attribute of `dist-sys-challenge`, which writes a workload's message types from its requests
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse::Parser, parse_macro_input, punctuated::Punctuated, spanned::Spanned, Attribute, Data, DeriveInput, Field,
+    Fields, Ident, LitStr, Meta, Token,
+};
+
+/// Turns an enum of requests into a workload's `Request` and `Response` types. Every request marked `#[ok(...)]`
+/// is paired with a response named after it with an `Ok` suffix, carrying the fields listed in the attribute.
+/// Requests without it are never answered.
+///
+/// ```ignore
+/// #[messages]
+/// pub enum Request {
+///     #[ok(echo: String)]
+///     Echo { echo: String },
+///     #[ok]
+///     Read,
+///     Gossip { values: Vec<i64> },
+/// }
+/// ```
+///
+/// Besides the `Request` enum, tagged by `type` in snake case, this writes:
+///
+/// - a struct per response, `EchoOk { echo }` and `ReadOk {}`,
+/// - a `Response` enum with a variant per response struct, tagged the same way,
+/// - `Request::TYPES` and `Response::TYPES`, the `type` of every variant,
+/// - an `Incoming` enum, returned by `Request::incoming`, that pairs every request with a
+///   `Reply` which only takes the response the request is paired with.
+///
+/// The requests and responses derive `JsonSchema` along with serde's traits, for the schemas `dist-sys schema`
+/// writes.
+///
+/// The names of the generated enums can be changed with `#[messages(response = Reply, incoming = Handle)]`.
+#[proc_macro_attribute]
+pub fn messages(args: TokenStream, input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let args = match Args::parse(args.into()) {
+        Ok(args) => args,
+        Err(err) => return err.into_compile_error().into(),
+    };
+    expand(args, input)
+        .unwrap_or_else(|err| err.into_compile_error())
+        .into()
+}
+
+struct Args {
+    response: Ident,
+    incoming: Ident,
+}
+
+impl Args {
+    fn parse(args: TokenStream2) -> syn::Result<Self> {
+        let mut parsed = Args {
+            response: Ident::new("Response", Span::call_site()),
+            incoming: Ident::new("Incoming", Span::call_site()),
+        };
+        let metas = Punctuated::<Meta, Token![,]>::parse_terminated.parse2(args)?;
+        for meta in metas {
+            let Meta::NameValue(pair) = &meta else {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected `response = Name` or `incoming = Name`",
+                ));
+            };
+            let syn::Expr::Path(value) = &pair.value else {
+                return Err(syn::Error::new(pair.value.span(), "expected a name"));
+            };
+            let value = value.path.require_ident()?.clone();
+            if pair.path.is_ident("response") {
+                parsed.response = value;
+            } else if pair.path.is_ident("incoming") {
+                parsed.incoming = value;
+            } else {
+                return Err(syn::Error::new(pair.path.span(), "unknown argument"));
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// One request along with the response it is answered with, if any
+struct Variant {
+    name: Ident,
+    kind: String,
+    fields: Vec<Field>,
+    ok: Option<Vec<Field>>,
+}
+
+fn expand(args: Args, mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &mut input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "#[messages] goes on an enum of requests",
+        ));
+    };
+
+    let mut variants = Vec::new();
+    for variant in &mut data.variants {
+        let fields = match &variant.fields {
+            Fields::Named(fields) => fields.named.iter().cloned().collect(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "requests have named fields, since they are JSON objects",
+                ))
+            }
+        };
+        let mut ok = None;
+        let mut attrs = Vec::new();
+        for attr in variant.attrs.drain(..) {
+            if attr.path().is_ident("ok") {
+                ok = Some(ok_fields(&attr)?);
+            } else {
+                attrs.push(attr);
+            }
+        }
+        variant.attrs = attrs;
+        let kind = serde_rename(&variant.attrs)?.unwrap_or_else(|| snake_case(&variant.ident.to_string()));
+        variants.push(Variant {
+            name: variant.ident.clone(),
+            kind,
+            fields,
+            ok,
+        });
+    }
+
+    let vis = &input.vis;
+    let request = &input.ident;
+    let response = &args.response;
+    let incoming = &args.incoming;
+    let request_types = variants.iter().map(|variant| &variant.kind);
+
+    // The schemas are for clients written in other languages, see `dist_sys_challenge::schema`
+    let derives = quote! {
+        #[derive(
+            Clone,
+            Debug,
+            ::serde::Serialize,
+            ::serde::Deserialize,
+            ::dist_sys_challenge::schemars::JsonSchema,
+        )]
+        #[schemars(crate = "::dist_sys_challenge::schemars")]
+    };
+    let answered: Vec<&Variant> = variants.iter().filter(|variant| variant.ok.is_some()).collect();
+    let ok_names: Vec<Ident> = answered
+        .iter()
+        .map(|variant| format_ident!("{}Ok", variant.name))
+        .collect();
+    let ok_types: Vec<String> = answered.iter().map(|variant| format!("{}_ok", variant.kind)).collect();
+    let ok_structs = answered.iter().zip(&ok_names).map(|(variant, name)| {
+        let fields = variant.ok.as_deref().unwrap_or_default();
+        let doc = format!("The reply to [`{request}::{}`]", variant.name);
+        quote! {
+            #[doc = #doc]
+            #derives
+            #vis struct #name {
+                #(#fields,)*
+            }
+
+            impl ::std::convert::From<#name> for #response {
+                fn from(response: #name) -> Self {
+                    #response::#name(response)
+                }
+            }
+        }
+    });
+
+    let incoming_enum = incoming_enum(vis, request, response, incoming, &variants);
+
+    Ok(quote! {
+        #derives
+        #[serde(tag = "type", rename_all = "snake_case")]
+        #input
+
+        impl #request {
+            /// The `type` of every request
+            #vis const TYPES: &'static [&'static str] = &[#(#request_types),*];
+        }
+
+        #derives
+        #[serde(tag = "type")]
+        #vis enum #response {
+            #(
+                #[serde(rename = #ok_types)]
+                #ok_names(#ok_names),
+            )*
+        }
+
+        impl #response {
+            /// The `type` of every response
+            #vis const TYPES: &'static [&'static str] = &[#(#ok_types),*];
+        }
+
+        #(#ok_structs)*
+
+        #incoming_enum
+    })
+}
+
+/// The enum pairing every request with the [`Reply`] it takes, and `Request::incoming` that builds it
+fn incoming_enum(
+    vis: &syn::Visibility,
+    request: &Ident,
+    response: &Ident,
+    incoming: &Ident,
+    variants: &[Variant],
+) -> TokenStream2 {
+    let workload = quote!(::dist_sys_challenge::workloads::workload);
+    let uses_fields = variants.iter().any(|variant| !variant.fields.is_empty());
+    let uses_reply = variants.iter().any(|variant| variant.ok.is_some());
+
+    let mut params = Vec::new();
+    let mut args = Vec::new();
+    if uses_fields {
+        params.push(quote!('r));
+        args.push(quote!('r));
+    }
+    if uses_reply {
+        params.push(quote!('a));
+        params.push(quote!(W: #workload::Workload<Response = #response> + ?Sized));
+        args.extend([quote!('a), quote!(W)]);
+    }
+    let (params, args) = if params.is_empty() {
+        (quote!(), quote!())
+    } else {
+        (quote!(<#(#params),*>), quote!(<#(#args),*>))
+    };
+
+    let mut definitions = Vec::new();
+    let mut arms = Vec::new();
+    for variant in variants {
+        let name = &variant.name;
+        let field_names: Vec<&Ident> = variant.fields.iter().filter_map(|field| field.ident.as_ref()).collect();
+        let field_types = variant.fields.iter().map(|field| &field.ty);
+        let reply = variant.ok.as_ref().map(|_| {
+            let ok = format_ident!("{}Ok", name);
+            quote!(reply: #workload::Reply<'a, W, #ok>,)
+        });
+        let build_reply = variant
+            .ok
+            .as_ref()
+            .map(|_| quote!(reply: #workload::Reply::new(factory),));
+        definitions.push(quote! {
+            #name { #(#field_names: &'r #field_types,)* #reply }
+        });
+        arms.push(quote! {
+            #request::#name { #(#field_names),* } => #incoming::#name { #(#field_names,)* #build_reply }
+        });
+    }
+
+    let doc = format!("A [`{request}`] along with the reply it takes, see [`{request}::incoming`]");
+    let receiver = if uses_fields { quote!(&'r self) } else { quote!(&self) };
+    let factory = uses_reply.then(|| quote!(factory: impl FnOnce(#response) -> #workload::Body<W> + 'a));
+    quote! {
+        #[doc = #doc]
+        #vis enum #incoming #params {
+            #(#definitions,)*
+        }
+
+        impl #request {
+            /// Pairs the request with the reply it takes, built by `factory`
+            #vis fn incoming #params (#receiver, #factory) -> #incoming #args {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+    }
+}
+
+/// The fields of the response in `#[ok(...)]`, none for a bare `#[ok]`
+fn ok_fields(attr: &Attribute) -> syn::Result<Vec<Field>> {
+    match &attr.meta {
+        Meta::Path(_) => Ok(Vec::new()),
+        Meta::List(list) => {
+            let parser = |input: syn::parse::ParseStream| {
+                Punctuated::<Field, Token![,]>::parse_terminated_with(input, Field::parse_named)
+            };
+            let fields = parser.parse2(list.tokens.clone())?;
+            Ok(fields.into_iter().collect())
+        }
+        Meta::NameValue(_) => Err(syn::Error::new(
+            attr.span(),
+            "expected `#[ok]` or `#[ok(field: Type, ...)]`",
+        )),
+    }
+}
+
+/// The name given by `#[serde(rename = "...")]`, if any
+fn serde_rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut rename = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(rename)
+}
+
+/// The name serde's `rename_all = "snake_case"` gives a variant
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.char_indices() {
+        if ch.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(ch.to_ascii_lowercase());
+    }
+    snake
+}
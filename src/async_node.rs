@@ -0,0 +1,620 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::{self, Instant};
+use tracing::{error, warn, Instrument, Span};
+
+use crate::{
+    logging,
+    message::{DecodeError, Error, ErrorCode, Message, MessageBody, MsgId, Payload, Raw, RawMessage},
+    metrics,
+    node::{acknowledge_init, decode_init, send, NodeId},
+    options::Options,
+    outbox,
+    rpc::RetryPolicy,
+    timer::Schedule,
+    workloads::workload::{Body, Workload},
+};
+
+/// A workload whose requests are handled concurrently on a tokio runtime, so that a handler can await rpcs to
+/// other nodes while the node keeps processing messages
+pub trait AsyncWorkload: Sized + Send + Sync + 'static {
+    type Request: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send + Sync + 'static;
+    type Response: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send + Sync + 'static;
+
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, ctx: Context<Self>) -> Self;
+
+    /// Handles request `msg_id` from `src`. A returned response is sent back to `src` and an error is sent back as
+    /// an `error` reply. Returning `None` leaves the request unanswered.
+    fn handle_request(
+        &self,
+        request: Self::Request,
+        src: NodeId,
+        msg_id: MsgId,
+    ) -> impl Future<Output = Result<Option<Self::Response>, Error>> + Send;
+
+    /// Handles a response that no rpc is waiting for, such as one to a request sent with [`Context::send`]
+    fn handle_response(&self, _response: &Self::Response, in_reply_to: MsgId, src: &NodeId) {
+        warn!(src, in_reply_to, "ignoring reply nobody is waiting for");
+    }
+
+    /// Handles an `error` reply that no rpc is waiting for
+    fn handle_error(&self, error: &Error, in_reply_to: MsgId, src: &NodeId) {
+        warn!(src, in_reply_to, %error, "request failed");
+    }
+}
+
+/// Lets the async node reuse [`Message`] for the types of an [`AsyncWorkload`]
+struct Wire<W>(PhantomData<W>);
+
+impl<W: AsyncWorkload> Payload for Wire<W> {
+    type Request = W::Request;
+    type Response = W::Response;
+}
+
+type ReplySender = oneshot::Sender<Result<serde_json::Value, Error>>;
+
+/// What the tasks of a node hand to its writer
+enum Output {
+    Line(Vec<u8>),
+    /// Asks to be told once every line before it is written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes the lines of a node in the order they were sent, so that no task blocks on the output
+async fn writer(mut lines: UnboundedReceiver<Output>, mut output: impl AsyncWrite + Unpin) {
+    while let Some(next) = lines.recv().await {
+        match next {
+            Output::Line(line) => {
+                let written = output.write_all(&line).await;
+                if let Err(err) = written.and(output.flush().await) {
+                    error!(%err, "writing output failed");
+                    return;
+                }
+            }
+            Output::Flush(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+struct Shared {
+    id: NodeId,
+    next_msg_id: AtomicUsize,
+    /// The rpcs waiting for a reply, `None` once the input is closed and no reply can come anymore
+    pending: Mutex<Option<HashMap<MsgId, ReplySender>>>,
+    output: UnboundedSender<Output>,
+    options: Options,
+}
+
+/// A workload's handle on its node, used to send requests and await their responses
+pub struct Context<W: AsyncWorkload> {
+    shared: Arc<Shared>,
+    workload: PhantomData<fn() -> W>,
+}
+
+impl<W: AsyncWorkload> Clone for Context<W> {
+    fn clone(&self) -> Self {
+        Context {
+            shared: self.shared.clone(),
+            workload: PhantomData,
+        }
+    }
+}
+
+/// Forgets an rpc when the future awaiting it completes or is dropped
+struct PendingGuard<'a> {
+    shared: &'a Shared,
+    msg_id: MsgId,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.shared.pending.lock().unwrap().as_mut() {
+            pending.remove(&self.msg_id);
+        }
+    }
+}
+
+fn shut_down<T>(_: T) -> Result<serde_json::Value, Error> {
+    Err(Error::new(ErrorCode::Crash, "node shut down"))
+}
+
+impl<W: AsyncWorkload> Context<W> {
+    fn new(id: NodeId, output: UnboundedSender<Output>, options: Options) -> Self {
+        Context {
+            shared: Arc::new(Shared {
+                id,
+                next_msg_id: AtomicUsize::new(0),
+                pending: Mutex::new(Some(HashMap::new())),
+                output,
+                options,
+            }),
+            workload: PhantomData,
+        }
+    }
+
+    /// The id of this node
+    pub fn id(&self) -> &NodeId {
+        &self.shared.id
+    }
+
+    /// The values of the flags the node was started with
+    pub fn options(&self) -> &Options {
+        &self.shared.options
+    }
+
+    fn send_body(&self, dest: NodeId, body: MessageBody<W::Request, W::Response>) {
+        let msg = Message::<Wire<W>> {
+            src: self.shared.id.clone(),
+            dest,
+            body,
+            trace: None,
+            clocks: None,
+        };
+        self.write(msg);
+    }
+
+    /// Hands `msg` to the writer. Once the writer is gone there is nowhere left to send it.
+    fn write<P: Payload>(&self, msg: Message<P>) {
+        let mut line = Vec::new();
+        send(&mut line, msg);
+        let _ = self.shared.output.send(Output::Line(line));
+    }
+
+    /// Waits until everything sent so far is written
+    async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.shared.output.send(Output::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+
+    /// Fails the rpcs still waiting and any made from now on, as no reply can arrive once the input is closed
+    fn close(&self) {
+        self.shared.pending.lock().unwrap().take();
+    }
+
+    fn next_msg_id(&self) -> MsgId {
+        self.shared.next_msg_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Sends a request without waiting for a response
+    pub fn send(&self, dest: NodeId, request: W::Request) {
+        let msg_id = self.next_msg_id();
+        self.send_body(dest, MessageBody::Request { msg_id, request });
+    }
+
+    /// Sends a request and waits for the response or `error` reply, however long that takes
+    pub async fn rpc(&self, dest: NodeId, request: W::Request) -> Result<W::Response, Error> {
+        self.call(dest, &request, None).await
+    }
+
+    /// Sends a request and resends it according to `retry` until `dest` answers. Fails with
+    /// [`ErrorCode::Timeout`] once the policy gives up.
+    pub async fn rpc_with_retry(
+        &self,
+        dest: NodeId,
+        request: W::Request,
+        retry: RetryPolicy,
+    ) -> Result<W::Response, Error> {
+        self.call(dest, &request, Some(retry)).await
+    }
+
+    /// Like [`Context::rpc`], but for a request outside of the workload's own message types, such as one to a
+    /// Maelstrom service. The reply is decoded as `R`.
+    pub fn call<R: DeserializeOwned>(
+        &self,
+        dest: NodeId,
+        request: &impl Serialize,
+        retry: Option<RetryPolicy>,
+    ) -> impl Future<Output = Result<R, Error>> + Send + '_ {
+        let request = serde_json::to_value(request).expect("serialize request");
+        async move {
+            let reply = self.exchange(self.next_msg_id(), dest, request, retry).await?;
+            serde_json::from_value(reply).map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))
+        }
+    }
+
+    /// Sends `request` as `msg_id` and waits for the reply
+    async fn exchange(
+        &self,
+        msg_id: MsgId,
+        dest: NodeId,
+        request: serde_json::Value,
+        retry: Option<RetryPolicy>,
+    ) -> Result<serde_json::Value, Error> {
+        let (reply_send, mut reply_recv) = oneshot::channel();
+        match self.shared.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(msg_id, reply_send),
+            None => return shut_down(()),
+        };
+        let _guard = PendingGuard {
+            shared: &self.shared,
+            msg_id,
+        };
+        let send_request = || {
+            let msg = Message::<Raw> {
+                src: self.shared.id.clone(),
+                dest: dest.clone(),
+                body: MessageBody::Request {
+                    msg_id,
+                    request: request.clone(),
+                },
+                trace: None,
+                clocks: None,
+            };
+            self.write(msg)
+        };
+
+        let Some(retry) = retry else {
+            send_request();
+            return reply_recv.await.unwrap_or_else(shut_down);
+        };
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            send_request();
+            match time::timeout(retry.timeout, &mut reply_recv).await {
+                Ok(reply) => return reply.unwrap_or_else(shut_down),
+                Err(_) if attempts < retry.max_attempts => {
+                    let backoff = retry.backoff(attempts, &mut rand::thread_rng());
+                    time::sleep(backoff).await;
+                    // A late reply to the previous attempt makes the resend unnecessary
+                    if let Ok(reply) = reply_recv.try_recv() {
+                        return reply;
+                    }
+                }
+                Err(_) => {
+                    let text = format!("no response to request {msg_id} after {attempts} attempts");
+                    return Err(Error::new(ErrorCode::Timeout, text));
+                }
+            }
+        }
+    }
+
+    fn reply(&self, dest: NodeId, in_reply_to: MsgId, response: W::Response) {
+        self.send_body(dest, MessageBody::Response { in_reply_to, response });
+    }
+
+    fn reply_error(&self, dest: NodeId, in_reply_to: MsgId, error: Error) {
+        self.send_body(dest, MessageBody::Error { in_reply_to, error });
+    }
+
+    fn reply_raw(&self, dest: NodeId, in_reply_to: MsgId, response: serde_json::Value) {
+        let msg = Message::<Raw> {
+            src: self.shared.id.clone(),
+            dest,
+            body: MessageBody::Response { in_reply_to, response },
+            trace: None,
+            clocks: None,
+        };
+        self.write(msg);
+    }
+
+    /// Hands a reply to the rpc waiting for it, or gives it back if nobody is
+    fn complete(&self, raw: RawMessage) -> Option<RawMessage> {
+        let Some(in_reply_to) = raw.in_reply_to() else {
+            return Some(raw);
+        };
+        let waiting = self.shared.pending.lock().unwrap().as_mut()?.remove(&in_reply_to);
+        match waiting {
+            Some(waiting) => {
+                let _ = waiting.send(raw.into_reply());
+                None
+            }
+            None => Some(raw),
+        }
+    }
+}
+
+/// The async counterpart of [`Node`](crate::node::Node). Every request is handled in its own task.
+///
+/// It has none of the node's metrics, journal, traces, clocks or interceptors: a `metrics` request is rejected with
+/// `not-supported`, and the other settings are ignored.
+pub struct AsyncNode<W: AsyncWorkload> {
+    /// The id of this node
+    pub id: NodeId,
+
+    span: Span,
+    workload: Arc<W>,
+    ctx: Context<W>,
+    lines: Lines<BufReader<Input>>,
+}
+
+type Input = Box<dyn AsyncRead + Unpin + Send>;
+
+async fn next_line(lines: &mut Lines<BufReader<Input>>) -> Option<String> {
+    loop {
+        match lines.next_line().await {
+            Ok(line) => return line,
+            Err(err) if err.kind() == ErrorKind::InvalidData => warn!(%err, "skipping input line"),
+            Err(err) => {
+                error!(%err, "reading stdin failed");
+                return None;
+            }
+        }
+    }
+}
+
+impl<W: AsyncWorkload> AsyncNode<W> {
+    /// Waits for init on stdin and starts the node, which then talks to Maelstrom through stdin and stdout. Returns
+    /// `None` if stdin closes before init arrives.
+    pub async fn init() -> Option<Self> {
+        Self::init_with(tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Like [`AsyncNode::init`], reading messages from `input` and writing to `output` instead of stdin and stdout.
+    /// Must be called on a runtime, which the node's writer is spawned on.
+    pub async fn init_with(
+        input: impl AsyncRead + Unpin + Send + 'static,
+        output: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> Option<Self> {
+        Self::init_with_options(input, output, Options::default()).await
+    }
+
+    /// Like [`AsyncNode::init_with`], handing the workload `options` through its [`Context`]
+    pub async fn init_with_options(
+        input: impl AsyncRead + Unpin + Send + 'static,
+        output: impl AsyncWrite + Unpin + Send + 'static,
+        options: Options,
+    ) -> Option<Self> {
+        let (output_send, output_recv) = unbounded_channel();
+        tokio::spawn(writer(output_recv, output));
+
+        let mut lines = BufReader::new(Box::new(input) as Input).lines();
+        let (src, msg_id, request) = loop {
+            let Some(line) = next_line(&mut lines).await else {
+                warn!("input closed before the node was initialized");
+                return None;
+            };
+            if let Some(init) = decode_init(&line) {
+                break init;
+            }
+        };
+        let mut ack = Vec::new();
+        acknowledge_init(&mut ack, &request, src, msg_id);
+        let _ = output_send.send(Output::Line(ack));
+
+        let ctx = Context::new(request.node_id.clone(), output_send, options);
+        let span = logging::node_span(&request.node_id);
+        let workload = span.in_scope(|| W::new(request.node_id.clone(), request.node_ids, ctx.clone()));
+        Some(AsyncNode {
+            id: request.node_id,
+            span,
+            workload: Arc::new(workload),
+            ctx,
+            lines,
+        })
+    }
+
+    pub async fn run(mut self) {
+        let mut handlers = JoinSet::new();
+        while let Some(line) = next_line(&mut self.lines).await {
+            while let Some(handled) = handlers.try_join_next() {
+                reap(handled);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let _entered = self.span.clone().entered();
+            let raw = match RawMessage::parse(&line) {
+                Ok(raw) => raw,
+                Err(err) => {
+                    warn!(line, %err, "ignoring message");
+                    continue;
+                }
+            };
+            logging::received(&raw.src, &raw.dest, &raw.body);
+            if raw.dest != self.id {
+                warn!(src = raw.src, dest = raw.dest, "ignoring message for another node");
+                continue;
+            }
+            let Some(raw) = self.ctx.complete(raw) else {
+                continue;
+            };
+            if raw.body["type"] == metrics::REQUEST_TYPE {
+                if let Some(msg_id) = raw.body["msg_id"].as_u64() {
+                    let error = Error::new(ErrorCode::NotSupported, "the async node keeps no metrics");
+                    self.ctx.reply_error(raw.src, msg_id as MsgId, error);
+                    continue;
+                }
+            }
+
+            let msg = match Message::<Wire<W>>::from_raw(raw) {
+                Ok(msg) => msg,
+                Err(DecodeError::Invalid(err)) => {
+                    warn!(line, %err, "ignoring message");
+                    continue;
+                }
+                Err(DecodeError::Rejected { src, msg_id, error }) => {
+                    warn!(src, msg_id, %error, "rejecting request");
+                    self.ctx.reply_error(src, msg_id, error);
+                    continue;
+                }
+            };
+            match msg.body {
+                MessageBody::Request { request, msg_id } => {
+                    let workload = self.workload.clone();
+                    let ctx = self.ctx.clone();
+                    let handled = async move {
+                        match workload.handle_request(request, msg.src.clone(), msg_id).await {
+                            Ok(Some(response)) => ctx.reply(msg.src, msg_id, response),
+                            Ok(None) => {}
+                            Err(error) => ctx.reply_error(msg.src, msg_id, error),
+                        }
+                    };
+                    handlers.spawn(handled.instrument(self.span.clone()));
+                }
+                MessageBody::Response { in_reply_to, response } => {
+                    self.workload.handle_response(&response, in_reply_to, &msg.src)
+                }
+                MessageBody::Error { in_reply_to, error } => self.workload.handle_error(&error, in_reply_to, &msg.src),
+            }
+        }
+        // Handlers still running get to send their replies, which must not be lost when the runtime shuts down
+        self.ctx.close();
+        while let Some(handled) = handlers.join_next().await {
+            reap(handled);
+        }
+        self.ctx.flush().await;
+    }
+}
+
+/// Logs a handler that panicked, which the node survives by dropping its request unanswered
+fn reap(handled: Result<(), JoinError>) {
+    if let Err(err) = handled {
+        error!(%err, "request handler failed");
+    }
+}
+
+/// Runs a synchronous [`Workload`] on an [`AsyncNode`]. Its handlers still run one at a time behind a lock, while a
+/// thread drains its outbox and turns rpcs and timers into tasks on the runtime.
+pub struct SyncAdapter<W: Workload> {
+    workload: Arc<Mutex<W>>,
+}
+
+impl<W> AsyncWorkload for SyncAdapter<W>
+where
+    W: Workload + Send + 'static,
+    W::Request: Sync + 'static,
+    W::Response: Sync + 'static,
+    W::Timer: 'static,
+{
+    type Request = W::Request;
+    type Response = W::Response;
+
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, ctx: Context<Self>) -> Self {
+        let (outbox_send, outbox_recv) = outbox::channel();
+        let workload = Arc::new(Mutex::new(W::new(id, all_nodes, outbox_send, ctx.options())));
+
+        let runtime = Handle::current();
+        let forwarded = workload.clone();
+        thread::spawn(move || forward_outbox(outbox_recv, forwarded, ctx, runtime));
+
+        SyncAdapter { workload }
+    }
+
+    async fn handle_request(
+        &self,
+        request: Self::Request,
+        src: NodeId,
+        msg_id: MsgId,
+    ) -> Result<Option<Self::Response>, Error> {
+        let dest = src.clone();
+        let response_factory = |response| Body::Response {
+            dest,
+            in_reply_to: msg_id,
+            response,
+        };
+        // The workload sends its response through the outbox itself
+        self.workload
+            .lock()
+            .unwrap()
+            .handle_request(&request, &src, response_factory)?;
+        Ok(None)
+    }
+
+    fn handle_response(&self, response: &Self::Response, in_reply_to: MsgId, src: &NodeId) {
+        self.workload
+            .lock()
+            .unwrap()
+            .handle_response(response, in_reply_to, src);
+    }
+
+    fn handle_error(&self, error: &Error, in_reply_to: MsgId, src: &NodeId) {
+        self.workload.lock().unwrap().handle_error(error, in_reply_to, src);
+    }
+}
+
+fn forward_outbox<W>(
+    outbox_recv: mpsc::Receiver<Body<W>>,
+    workload: Arc<Mutex<W>>,
+    ctx: Context<SyncAdapter<W>>,
+    runtime: Handle,
+) where
+    W: Workload + Send + 'static,
+    W::Request: Sync + 'static,
+    W::Response: Sync + 'static,
+    W::Timer: 'static,
+{
+    for body in outbox_recv {
+        match body {
+            Body::Request { dest, request } => ctx.send(dest, request),
+            Body::Rpc {
+                dest,
+                request,
+                retry,
+                callback,
+            } => {
+                let ctx = ctx.clone();
+                let workload = workload.clone();
+                runtime.spawn(async move {
+                    let response = match retry {
+                        Some(retry) => ctx.rpc_with_retry(dest.clone(), request, retry).await,
+                        None => ctx.rpc(dest.clone(), request).await,
+                    };
+                    callback(&mut workload.lock().unwrap(), response, &dest);
+                });
+            }
+            Body::Call {
+                dest,
+                request,
+                retry,
+                callback,
+            } => {
+                let ctx = ctx.clone();
+                let workload = workload.clone();
+                runtime.spawn(async move {
+                    let msg_id = ctx.next_msg_id();
+                    let reply = ctx.exchange(msg_id, dest.clone(), request, retry).await;
+                    callback(&mut workload.lock().unwrap(), reply, &dest, msg_id);
+                });
+            }
+            Body::Response {
+                dest,
+                in_reply_to,
+                response,
+            } => ctx.reply(dest, in_reply_to, response),
+            Body::Error {
+                dest,
+                in_reply_to,
+                error,
+            } => ctx.reply_error(dest, in_reply_to, error),
+            Body::Reply {
+                dest,
+                in_reply_to,
+                body,
+            } => ctx.reply_raw(dest, in_reply_to, body),
+            Body::Timer { timer, schedule } => {
+                let workload = workload.clone();
+                runtime.spawn(async move {
+                    match schedule.clamped() {
+                        Schedule::Once(delay) => {
+                            time::sleep(delay).await;
+                            workload.lock().unwrap().handle_timer(timer);
+                        }
+                        Schedule::Every(period) => {
+                            let mut interval = time::interval_at(Instant::now() + period, period);
+                            loop {
+                                interval.tick().await;
+                                workload.lock().unwrap().handle_timer(timer.clone());
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    node::NodeId,
+    options::{Flag, Kind},
+    outbox::Stamped,
+    workloads::workload::Workload,
+};
+
+/// How long requests to a node are held back so that they go out together, merged where the workload allows. Off
+/// by default, and only plain [`Body::Request`](crate::workloads::workload::Body::Request)s are ever held back.
+pub const BATCH_WINDOW: Flag = Flag {
+    name: "batch-window",
+    description: "how long requests to a node are held back to be sent together, 0 to send right away",
+    default: "0ms",
+    kind: Kind::Duration,
+};
+
+/// Requests held back in the outbox, by destination
+pub(crate) struct Batches<W: Workload> {
+    window: Duration,
+    queued: BTreeMap<NodeId, Queued<Stamped<W::Request>>>,
+}
+
+struct Queued<R> {
+    due: Instant,
+    requests: Vec<R>,
+}
+
+impl<W: Workload> Batches<W> {
+    pub(crate) fn new(window: Duration) -> Self {
+        Batches {
+            window,
+            queued: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Holds `request` back until the window of `dest` closes, merging it into the last request waiting for `dest`
+    /// if [`Workload::coalesce`] allows. Only the last one is tried, so that requests to a node keep their order. A
+    /// merged request takes the trace and clocks of the newer one.
+    pub(crate) fn push(&mut self, dest: NodeId, request: Stamped<W::Request>, now: Instant) {
+        let window = self.window;
+        let queued = self.queued.entry(dest).or_insert_with(|| Queued {
+            due: now + window,
+            requests: Vec::new(),
+        });
+        let Stamped {
+            body: request,
+            trace,
+            clocks,
+        } = request;
+        let request = match queued.requests.last_mut() {
+            Some(last) => match W::coalesce(&mut last.body, request) {
+                Ok(()) => {
+                    last.trace = trace;
+                    last.clocks = clocks;
+                    return;
+                }
+                Err(unmerged) => unmerged,
+            },
+            None => request,
+        };
+        queued.requests.push(Stamped {
+            body: request,
+            trace,
+            clocks,
+        });
+    }
+
+    /// Everything waiting for `dest`, whether or not its window has closed
+    pub(crate) fn take(&mut self, dest: &str) -> Vec<Stamped<W::Request>> {
+        self.queued
+            .remove(dest)
+            .map(|queued| queued.requests)
+            .unwrap_or_default()
+    }
+
+    /// Everything waiting for a destination whose window has closed by `now`
+    pub(crate) fn due(&mut self, now: Instant) -> Vec<(NodeId, Vec<Stamped<W::Request>>)> {
+        let due: Vec<NodeId> = self
+            .queued
+            .iter()
+            .filter(|(_, queued)| queued.due <= now)
+            .map(|(dest, _)| dest.clone())
+            .collect();
+        due.into_iter()
+            .map(|dest| {
+                let requests = self.take(&dest);
+                (dest, requests)
+            })
+            .collect()
+    }
+
+    /// Everything waiting, for when the node shuts down
+    pub(crate) fn drain(&mut self) -> Vec<(NodeId, Vec<Stamped<W::Request>>)> {
+        std::mem::take(&mut self.queued)
+            .into_iter()
+            .map(|(dest, queued)| (dest, queued.requests))
+            .collect()
+    }
+
+    /// When the next window closes
+    pub(crate) fn next_wakeup(&self) -> Option<Instant> {
+        self.queued.values().map(|queued| queued.due).min()
+    }
+}
@@ -0,0 +1,39 @@
+use dist_sys_challenge::{
+    logging,
+    registry::{self, Invocation, WORKLOAD_VAR},
+    schema,
+};
+use std::path::Path;
+use std::process::exit;
+
+/// Runs any of the workloads, picked by the first argument or by `DIST_SYS_WORKLOAD`, e.g. `dist-sys broadcast`.
+/// `dist-sys list` lists them along with their flags, and `dist-sys schema <dir>` writes the JSON Schemas of their
+/// messages.
+fn main() {
+    logging::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if matches!(args.as_slice(), [arg] if ["list", "help", "--help", "-h"].contains(&arg.as_str())) {
+        print!("{}", registry::usage());
+        return;
+    }
+    if let [command, dir] = args.as_slice() {
+        if command == "schema" {
+            match schema::write_all(Path::new(dir)) {
+                Ok(written) => written.iter().for_each(|path| println!("{}", path.display())),
+                Err(err) => {
+                    eprintln!("writing schemas to {dir} failed: {err}");
+                    exit(1);
+                }
+            }
+            return;
+        }
+    }
+    let env_workload = std::env::var(WORKLOAD_VAR).ok();
+    match Invocation::parse(&args, env_workload.as_deref()) {
+        Ok(invocation) => invocation.run(),
+        Err(err) => {
+            eprintln!("{err}\n\n{}", registry::usage());
+            exit(2);
+        }
+    }
+}
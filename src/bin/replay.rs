@@ -0,0 +1,60 @@
+use dist_sys_challenge::{
+    journal::{self, Difference, Direction},
+    logging, registry,
+};
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "usage: replay [--fast] <workload> <journal>, see `dist-sys list` for the workloads";
+
+/// Replays the messages a node received, as recorded in a journal, into a fresh node and prints how what it sends
+/// differs from what was recorded. Exits with 1 if it differs at all, and with 2 if the journal cannot be replayed.
+fn main() {
+    logging::init();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let fast = args.first().is_some_and(|arg| arg == "--fast");
+    if fast {
+        args.remove(0);
+    }
+    let [workload, path] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+    let recorded = match journal::read(Path::new(path)) {
+        Ok(recorded) => recorded,
+        Err(err) => {
+            eprintln!("reading {path} failed: {err}");
+            exit(2);
+        }
+    };
+
+    let Some(registered) = registry::find(workload) else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+    let Some(replayed) = registered.replay(&recorded, fast) else {
+        eprintln!("{path} has no init to start the node with");
+        exit(2);
+    };
+
+    let differences = journal::diff(&recorded, &replayed);
+    for difference in &differences {
+        match difference {
+            Difference::Missing { dest, body } => println!("- {dest} {body}"),
+            Difference::Extra { dest, body } => println!("+ {dest} {body}"),
+        }
+    }
+    println!(
+        "{} sent {} messages, replayed {}, {} differences",
+        workload,
+        recorded
+            .iter()
+            .filter(|entry| entry.direction == Direction::Sent)
+            .count(),
+        replayed.len(),
+        differences.len()
+    );
+    if !differences.is_empty() {
+        exit(1);
+    }
+}
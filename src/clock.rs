@@ -0,0 +1,159 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::node::NodeId;
+
+/// A Lamport clock: a counter that moves past every timestamp it sees, so that an event caused by another always
+/// gets the later time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Lamport(pub u64);
+
+impl Lamport {
+    /// Advances the clock for a local event and returns its time
+    pub fn tick(&mut self) -> Lamport {
+        self.0 += 1;
+        *self
+    }
+
+    /// Advances the clock past `seen`, for receiving a message stamped with it
+    pub fn observe(&mut self, seen: Lamport) -> Lamport {
+        self.0 = self.0.max(seen.0);
+        self.tick()
+    }
+}
+
+/// A version vector: how many versions of each node's state have been seen. Vectors are only partially ordered,
+/// with `partial_cmp` returning `None` for concurrent ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<NodeId, u64>);
+
+impl VersionVector {
+    /// The version of `node`, 0 if none was seen
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or_default()
+    }
+
+    /// Counts a new version of `node` and returns it
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let version = self.0.entry(node.to_string()).or_default();
+        *version += 1;
+        *version
+    }
+
+    /// Takes in every version `other` has seen
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (node, version) in &other.0 {
+            let mine = self.0.entry(node.clone()).or_default();
+            *mine = (*mine).max(*version);
+        }
+    }
+
+    /// Whether neither vector has seen everything the other has
+    pub fn concurrent_with(&self, other: &VersionVector) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NodeId, u64)> {
+        self.0.iter().map(|(node, version)| (node, *version))
+    }
+}
+
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let nodes = self.0.keys().chain(other.0.keys());
+        let (mut less, mut greater) = (false, false);
+        for node in nodes {
+            match self.get(node).cmp(&other.get(node)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// The clocks a node keeps, and the stamp carried by the messages it sends to other nodes
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Clocks {
+    pub lamport: Lamport,
+
+    /// Counts the messages each node sent to other nodes
+    pub vector: VersionVector,
+}
+
+/// The clocks of a node whose workload keeps them, see [`Workload::CLOCKS`](crate::workloads::workload::Workload::CLOCKS).
+/// Shared between the node, which advances them on receipt, and the outbox, which stamps what is sent.
+pub(crate) struct Stamper {
+    id: NodeId,
+    nodes: HashSet<NodeId>,
+    clocks: Mutex<Clocks>,
+}
+
+impl Stamper {
+    pub(crate) fn new(id: NodeId, nodes: HashSet<NodeId>) -> Self {
+        Stamper {
+            id,
+            nodes,
+            clocks: Mutex::new(Clocks::default()),
+        }
+    }
+
+    /// Sending to `dest` is an event of its own when `dest` is a node, which the message is stamped with.
+    /// Messages to clients are left alone.
+    pub(crate) fn stamp(&self, dest: &str) -> Option<Clocks> {
+        if !self.nodes.contains(dest) {
+            return None;
+        }
+        let mut clocks = self.clocks.lock().unwrap();
+        clocks.lamport.tick();
+        clocks.vector.increment(&self.id);
+        Some(clocks.clone())
+    }
+
+    /// Advances the clocks past the stamp of a message received from another node
+    pub(crate) fn receive(&self, stamp: &Clocks) {
+        let mut clocks = self.clocks.lock().unwrap();
+        clocks.lamport.observe(stamp.lamport);
+        clocks.vector.merge(&stamp.vector);
+    }
+
+    fn now(&self) -> Clocks {
+        self.clocks.lock().unwrap().clone()
+    }
+}
+
+thread_local! {
+    static HANDLING: RefCell<Option<(Arc<Stamper>, Option<Clocks>)>> = const { RefCell::new(None) };
+}
+
+/// Runs a handler of the node keeping `stamper`, for a message stamped with `received` if any
+pub(crate) fn within<T>(stamper: Option<Arc<Stamper>>, received: Option<Clocks>, f: impl FnOnce() -> T) -> T {
+    let handling = stamper.map(|stamper| (stamper, received));
+    let previous = HANDLING.with(|current| current.replace(handling));
+    let result = f();
+    HANDLING.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// The clocks of the node whose handler is running, with every message sent so far counted. `None` outside of a
+/// handler or for a workload that keeps no clocks.
+pub fn now() -> Option<Clocks> {
+    HANDLING.with(|current| current.borrow().as_ref().map(|(stamper, _)| stamper.now()))
+}
+
+/// The stamp of the message being handled, if it came from another node
+pub fn received() -> Option<Clocks> {
+    HANDLING.with(|current| current.borrow().as_ref().and_then(|(_, received)| received.clone()))
+}
@@ -0,0 +1,348 @@
+use rand::{rngs::StdRng, SeedableRng};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{
+    faults::{FaultChange, FaultScript, Faults},
+    message::MsgId,
+    node::{Node, NodeId},
+    options::Options,
+    transport::Channel,
+    workloads::workload::Workload,
+};
+
+/// How long a client waits for a reply before giving up
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The client that initializes the nodes, reserved by the cluster
+const INIT_CLIENT: &str = "c0";
+
+/// What the network thread is handed
+enum Traffic {
+    /// A line a node wrote
+    Line(String),
+
+    /// Changes to the faults, by when they are due
+    Script(Vec<(Instant, FaultChange)>),
+}
+
+impl From<String> for Traffic {
+    fn from(line: String) -> Self {
+        Traffic::Line(line)
+    }
+}
+
+/// Where the router delivers a message, by the id in its `dest`
+#[derive(Default)]
+struct Routes {
+    nodes: HashMap<NodeId, Sender<String>>,
+    clients: HashMap<NodeId, Sender<(NodeId, Value)>>,
+
+    /// Set once the cluster is dropped, after which messages still in flight are dropped quietly
+    closed: bool,
+}
+
+impl Routes {
+    fn is_node(&self, id: &str) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    fn deliver(&self, line: String) {
+        let msg: Value = match serde_json::from_str(&line) {
+            Ok(msg) => msg,
+            Err(err) => {
+                eprintln!("cluster: dropping {line:?}: {err}");
+                return;
+            }
+        };
+        let dest = msg["dest"].as_str().unwrap_or_default();
+        if let Some(node) = self.nodes.get(dest) {
+            let _ = node.send(line);
+        } else if let Some(client) = self.clients.get(dest) {
+            let src = msg["src"].as_str().unwrap_or_default().to_string();
+            let _ = client.send((src, msg["body"].clone()));
+        } else if !self.closed {
+            eprintln!("cluster: dropping message for unknown {dest:?}: {line}");
+        }
+    }
+}
+
+/// A message held back by the faults of its link
+struct Delayed {
+    at: Instant,
+    seq: u64,
+    line: String,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+/// Routes the lines written by the nodes, passing messages between nodes through `faults`
+fn network_thread(traffic: Receiver<Traffic>, routes: Arc<Mutex<Routes>>, faults: Arc<Mutex<Faults>>) {
+    let mut rng = StdRng::from_entropy();
+    let mut delayed = BinaryHeap::new();
+    let mut next_seq = 0;
+    let mut script: Vec<(Instant, FaultChange)> = Vec::new();
+    loop {
+        let now = Instant::now();
+        while script.first().is_some_and(|(at, _)| *at <= now) {
+            let (_, change) = script.remove(0);
+            change(&mut faults.lock().unwrap());
+        }
+        while delayed
+            .peek()
+            .is_some_and(|Reverse(msg): &Reverse<Delayed>| msg.at <= now)
+        {
+            let Reverse(msg) = delayed.pop().expect("peeked message");
+            routes.lock().unwrap().deliver(msg.line);
+        }
+
+        let wakeup = [
+            delayed.peek().map(|Reverse(msg)| msg.at),
+            script.first().map(|(at, _)| *at),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let received = match wakeup {
+            Some(wakeup) => match traffic.recv_timeout(wakeup.saturating_duration_since(Instant::now())) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+            None => match traffic.recv() {
+                Ok(received) => received,
+                Err(_) => return,
+            },
+        };
+
+        let line = match received {
+            Traffic::Line(line) => line,
+            Traffic::Script(steps) => {
+                script.extend(steps);
+                // Stable, so that steps at the same time keep their order
+                script.sort_by_key(|(at, _)| *at);
+                continue;
+            }
+        };
+        let Ok(msg) = serde_json::from_str::<Value>(&line) else {
+            routes.lock().unwrap().deliver(line);
+            continue;
+        };
+        let src = msg["src"].as_str().unwrap_or_default();
+        let dest = msg["dest"].as_str().unwrap_or_default();
+        let between_nodes = {
+            let routes = routes.lock().unwrap();
+            routes.is_node(src) && routes.is_node(dest)
+        };
+        if !between_nodes {
+            routes.lock().unwrap().deliver(line);
+            continue;
+        }
+        for delay in faults.lock().unwrap().plan(src, dest, &mut rng) {
+            if delay.is_zero() {
+                routes.lock().unwrap().deliver(line.clone());
+            } else {
+                delayed.push(Reverse(Delayed {
+                    at: Instant::now() + delay,
+                    seq: next_seq,
+                    line: line.clone(),
+                }));
+                next_seq += 1;
+            }
+        }
+    }
+}
+
+/// Runs `n` nodes of a workload in this process, each on its own threads, with messages between them routed in
+/// memory. Nodes are named `n1`, `n2`... and initialized before [`Cluster::start`] returns. Tests talk to them
+/// through [`Client`]s, which stand in for Maelstrom's clients. Dropping the cluster closes every node's input.
+pub struct Cluster {
+    nodes: Vec<NodeId>,
+    routes: Arc<Mutex<Routes>>,
+    faults: Arc<Mutex<Faults>>,
+
+    /// The network thread's input, for fault scripts
+    traffic: Sender<Traffic>,
+}
+
+impl Cluster {
+    pub fn start<W: Workload + Send + 'static>(n: usize) -> Self {
+        Self::start_with_options::<W>(n, |_| Options::default())
+    }
+
+    /// Like [`Cluster::start`], starting each node with the options `options` gives for its id
+    pub fn start_with_options<W: Workload + Send + 'static>(n: usize, options: impl Fn(&str) -> Options) -> Self {
+        let nodes: Vec<NodeId> = (1..=n).map(|i| format!("n{i}")).collect();
+        let routes = Arc::new(Mutex::new(Routes::default()));
+
+        let (output_send, output_recv) = mpsc::channel();
+        for id in &nodes {
+            let (input_send, input_recv) = mpsc::channel();
+            routes.lock().unwrap().nodes.insert(id.clone(), input_send);
+            let link = Channel {
+                input: input_recv,
+                output: output_send.clone(),
+            };
+            let builder = Node::<W>::builder().options(options(id));
+            thread::spawn(move || builder.init_with(link).map(Node::run));
+        }
+
+        let faults = Arc::new(Mutex::new(Faults::default()));
+        let network_routes = routes.clone();
+        let network_faults = faults.clone();
+        thread::spawn(move || network_thread(output_recv, network_routes, network_faults));
+
+        let cluster = Cluster {
+            nodes,
+            routes,
+            faults,
+            traffic: output_send,
+        };
+        let mut init = cluster.client(INIT_CLIENT);
+        for id in &cluster.nodes {
+            let reply = init.rpc(id, json!({"type": "init", "node_id": id, "node_ids": cluster.nodes}));
+            assert_eq!(reply["type"], "init_ok", "{id} did not acknowledge init");
+        }
+        cluster
+    }
+
+    /// The ids of the nodes in the cluster
+    pub fn nodes(&self) -> &[NodeId] {
+        &self.nodes
+    }
+
+    /// The faults of the network between the nodes, which apply to messages sent from now on
+    pub fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap()
+    }
+
+    /// Applies the changes in `script` at their times, counted from now
+    pub fn script(&self, script: FaultScript) {
+        let now = Instant::now();
+        let steps = script
+            .into_steps()
+            .into_iter()
+            .map(|(at, change)| (now + at, change))
+            .collect();
+        self.traffic
+            .send(Traffic::Script(steps))
+            .expect("network thread running");
+    }
+
+    /// Registers a client with the given id, which must not be the id of a node or of another client
+    pub fn client(&self, id: impl Into<NodeId>) -> Client {
+        let id = id.into();
+        let (inbox_send, inbox_recv) = mpsc::channel();
+        let mut routes = self.routes.lock().unwrap();
+        assert!(!routes.nodes.contains_key(&id), "{id} is a node");
+        assert!(
+            routes.clients.insert(id.clone(), inbox_send).is_none(),
+            "{id} is already a client"
+        );
+        Client {
+            id,
+            next_msg_id: 0,
+            routes: self.routes.clone(),
+            inbox: inbox_recv,
+            unclaimed: Vec::new(),
+        }
+    }
+}
+
+impl Drop for Cluster {
+    fn drop(&mut self) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.closed = true;
+        routes.nodes.clear();
+    }
+}
+
+/// A client of a [`Cluster`], sending requests to its nodes and receiving the replies addressed to it
+pub struct Client {
+    id: NodeId,
+    next_msg_id: MsgId,
+    routes: Arc<Mutex<Routes>>,
+    inbox: Receiver<(NodeId, Value)>,
+
+    /// Messages received while waiting for a different reply
+    unclaimed: Vec<(NodeId, Value)>,
+}
+
+impl Client {
+    pub fn id(&self) -> &NodeId {
+        &self.id
+    }
+
+    /// Sends `body` to `dest` with a fresh `msg_id`, which is returned
+    pub fn send(&mut self, dest: &str, mut body: Value) -> MsgId {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        body["msg_id"] = msg_id.into();
+        let line = json!({"src": self.id, "dest": dest, "body": body}).to_string();
+        self.routes.lock().unwrap().deliver(line);
+        msg_id
+    }
+
+    /// Sends `body` to `dest` and waits for the reply, panicking if none arrives in time
+    pub fn rpc(&mut self, dest: &str, body: Value) -> Value {
+        let msg_id = self.send(dest, body);
+        self.reply(dest, msg_id)
+    }
+
+    /// Waits for the reply from `src` to request `msg_id`, panicking if none arrives in time
+    pub fn reply(&mut self, src: &str, msg_id: MsgId) -> Value {
+        let is_reply = |(from, body): &(NodeId, Value)| from == src && body["in_reply_to"] == msg_id;
+        if let Some(i) = self.unclaimed.iter().position(is_reply) {
+            return self.unclaimed.remove(i).1;
+        }
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        loop {
+            match self
+                .inbox
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(msg) if is_reply(&msg) => return msg.1,
+                Ok(msg) => self.unclaimed.push(msg),
+                Err(RecvTimeoutError::Timeout) => panic!("{} got no reply from {src} to request {msg_id}", self.id),
+                Err(RecvTimeoutError::Disconnected) => unreachable!("the cluster keeps every client's inbox"),
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for the next message not claimed through [`Client::reply`], as `(src, body)`
+    pub fn recv(&mut self, timeout: Duration) -> Option<(NodeId, Value)> {
+        if !self.unclaimed.is_empty() {
+            return Some(self.unclaimed.remove(0));
+        }
+        self.inbox.recv_timeout(timeout).ok()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.routes.lock().unwrap().clients.remove(&self.id);
+    }
+}
@@ -0,0 +1,160 @@
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::node::NodeId;
+
+/// How long a reordered message is held back on top of its latency, so that later messages overtake it
+const REORDER_DELAY: Duration = Duration::from_millis(100);
+
+/// How long a message between two nodes takes on top of the normal delivery time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Latency {
+    Fixed(Duration),
+
+    /// Drawn uniformly from the inclusive range
+    Uniform(Duration, Duration),
+
+    /// Drawn from an exponential distribution with the given mean, giving a long tail of slow messages
+    Exponential(Duration),
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Fixed(Duration::ZERO)
+    }
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut impl Rng) -> Duration {
+        match *self {
+            Latency::Fixed(latency) => latency,
+            Latency::Uniform(min, max) => rng.gen_range(min..=max.max(min)),
+            Latency::Exponential(mean) => mean.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+/// The faults of the messages sent over one link. Probabilities are between 0 and 1.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkFaults {
+    /// Probability that a message is lost
+    pub drop: f64,
+
+    /// Probability that a message is delivered twice, each copy with its own latency
+    pub duplicate: f64,
+
+    /// Probability that a message is held back long enough for later messages to overtake it
+    pub reorder: f64,
+
+    pub latency: Latency,
+}
+
+/// The faults of the network between nodes: partitions, and the [`LinkFaults`] of every link. Messages from and to
+/// clients are never affected.
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    default: LinkFaults,
+
+    /// Overrides of the default, by `(src, dest)`
+    links: BTreeMap<(NodeId, NodeId), LinkFaults>,
+
+    /// The group of every partitioned node. Nodes in no group reach everyone.
+    groups: BTreeMap<NodeId, usize>,
+}
+
+impl Faults {
+    /// Applies `faults` to every link without faults of its own
+    pub fn set_default(&mut self, faults: LinkFaults) {
+        self.default = faults;
+    }
+
+    /// Applies `faults` to the messages from `src` to `dest`, but not the other way around
+    pub fn set_link(&mut self, src: impl Into<NodeId>, dest: impl Into<NodeId>, faults: LinkFaults) {
+        self.links.insert((src.into(), dest.into()), faults);
+    }
+
+    /// Returns every link to the default faults
+    pub fn clear_links(&mut self) {
+        self.links.clear();
+    }
+
+    /// Splits the nodes into `groups` that cannot reach each other, replacing any previous partition
+    pub fn partition<I>(&mut self, groups: impl IntoIterator<Item = I>)
+    where
+        I: IntoIterator,
+        I::Item: Into<NodeId>,
+    {
+        self.groups.clear();
+        for (group, nodes) in groups.into_iter().enumerate() {
+            for node in nodes {
+                self.groups.insert(node.into(), group);
+            }
+        }
+    }
+
+    /// Removes the partition
+    pub fn heal(&mut self) {
+        self.groups.clear();
+    }
+
+    pub fn is_partitioned(&self, src: &str, dest: &str) -> bool {
+        match (self.groups.get(src), self.groups.get(dest)) {
+            (Some(src), Some(dest)) => src != dest,
+            _ => false,
+        }
+    }
+
+    /// Decides the fate of a message from node `src` to node `dest`, returning the extra delay of every copy to
+    /// deliver. A lost message has none.
+    pub(crate) fn plan(&self, src: &str, dest: &str, rng: &mut impl Rng) -> Vec<Duration> {
+        if self.is_partitioned(src, dest) {
+            return Vec::new();
+        }
+        let faults = self
+            .links
+            .get(&(src.to_string(), dest.to_string()))
+            .unwrap_or(&self.default);
+        if rng.gen_bool(faults.drop) {
+            return Vec::new();
+        }
+        let copies = if rng.gen_bool(faults.duplicate) { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let delay = faults.latency.sample(rng);
+                if rng.gen_bool(faults.reorder) {
+                    delay + REORDER_DELAY
+                } else {
+                    delay
+                }
+            })
+            .collect()
+    }
+}
+
+/// A change to the [`Faults`] of a network
+pub type FaultChange = Box<dyn FnOnce(&mut Faults) + Send>;
+
+/// Changes to the faults of a network at set times, such as a partition that heals after a while
+#[derive(Default)]
+pub struct FaultScript {
+    steps: Vec<(Duration, FaultChange)>,
+}
+
+impl FaultScript {
+    pub fn new() -> Self {
+        FaultScript::default()
+    }
+
+    /// Applies `change` once `at` has passed since the script was started
+    pub fn at(mut self, at: Duration, change: impl FnOnce(&mut Faults) + Send + 'static) -> Self {
+        self.steps.push((at, Box::new(change)));
+        self
+    }
+
+    /// The steps in the order they are applied, steps at the same time in the order they were added
+    pub(crate) fn into_steps(mut self) -> Vec<(Duration, FaultChange)> {
+        self.steps.sort_by_key(|(at, _)| *at);
+        self.steps
+    }
+}
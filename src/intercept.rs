@@ -0,0 +1,76 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+use crate::message::RawMessage;
+
+/// Sees every message a node receives or writes, for concerns that cut across workloads such as logging,
+/// authentication, fault injection or deduplication. Registered with [`Node::intercept`](crate::node::Node::intercept).
+pub trait Interceptor: Send {
+    /// Sees a message addressed to the node, before the node handles it. Runs on the node's thread.
+    fn inbound(&mut self, message: RawMessage) -> Verdict {
+        Verdict::Pass(message)
+    }
+
+    /// Sees a message the node is about to write, `msg_id` included. Runs on the thread writing the outbox.
+    fn outbound(&mut self, message: RawMessage) -> Verdict {
+        Verdict::Pass(message)
+    }
+}
+
+/// What an [`Interceptor`] does with a message
+#[derive(Debug)]
+pub enum Verdict {
+    /// Hands the message on, changed or not
+    Pass(RawMessage),
+
+    /// Drops the message without a trace
+    Drop,
+
+    /// Drops the message and answers it with this body in its place. An inbound request is answered to its sender,
+    /// and an outbound one is answered to the node as if its destination had replied. `in_reply_to` is filled in,
+    /// and a message that is not a request is only dropped. A body that is not a JSON object is dropped with a warning.
+    Answer(Value),
+}
+
+/// The interceptors of a node, shared between the node and its outbox
+#[derive(Clone, Default)]
+pub(crate) struct Chain {
+    interceptors: Arc<Mutex<Vec<Box<dyn Interceptor>>>>,
+}
+
+impl Chain {
+    pub(crate) fn push(&self, interceptor: Box<dyn Interceptor>) {
+        self.interceptors.lock().unwrap().push(interceptor);
+    }
+
+    /// Runs `message` through every interceptor in the order they were registered, until one doesn't pass it on
+    pub(crate) fn inbound(&self, message: RawMessage) -> Verdict {
+        let mut interceptors = self.interceptors.lock().unwrap();
+        run(interceptors.iter_mut(), message, |interceptor, message| {
+            interceptor.inbound(message)
+        })
+    }
+
+    /// Like [`Chain::inbound`] in reverse order, so that the first interceptor registered is the closest to the
+    /// network both ways
+    pub(crate) fn outbound(&self, message: RawMessage) -> Verdict {
+        let mut interceptors = self.interceptors.lock().unwrap();
+        run(interceptors.iter_mut().rev(), message, |interceptor, message| {
+            interceptor.outbound(message)
+        })
+    }
+}
+
+fn run<'a>(
+    interceptors: impl Iterator<Item = &'a mut Box<dyn Interceptor>>,
+    mut message: RawMessage,
+    intercept: impl Fn(&mut dyn Interceptor, RawMessage) -> Verdict,
+) -> Verdict {
+    for interceptor in interceptors {
+        match intercept(interceptor.as_mut(), message) {
+            Verdict::Pass(next) => message = next,
+            verdict => return verdict,
+        }
+    }
+    Verdict::Pass(message)
+}
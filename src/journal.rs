@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+use crate::{
+    message::{MsgId, RawMessage},
+    node::{Node, NodeId},
+    transport::Channel,
+    workloads::workload::Workload,
+};
+
+/// The environment variable naming the file [`Node::init`] records its journal to
+pub const JOURNAL_VAR: &str = "NODE_JOURNAL";
+
+/// How long replaying waits for the node to send the request a recorded reply answers
+const REPLY_WAIT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// One line of a journal
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Microseconds since the journal was created
+    pub at_us: u64,
+    pub direction: Direction,
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub body: Value,
+}
+
+impl Entry {
+    fn in_reply_to(&self) -> Option<MsgId> {
+        self.body["in_reply_to"].as_u64().map(|id| id as MsgId)
+    }
+
+    fn line(&self) -> String {
+        serde_json::json!({"src": self.src, "dest": self.dest, "body": self.body}).to_string()
+    }
+}
+
+/// Appends every message a node receives or sends to a file, one JSON [`Entry`] per line
+pub struct Journal {
+    start: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+impl Journal {
+    /// Creates the journal at `path`, replacing whatever was there
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Journal {
+            start: Instant::now(),
+            file: Mutex::new(BufWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// The journal at the path in [`JOURNAL_VAR`], if it is set. A journal that cannot be created is logged and
+    /// left out rather than keeping the node from running.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(JOURNAL_VAR)?;
+        match Journal::create(Path::new(&path)) {
+            Ok(journal) => Some(journal),
+            Err(err) => {
+                error!(path = ?path, %err, "not recording a journal");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn record(&self, direction: Direction, message: &RawMessage) {
+        let entry = Entry {
+            at_us: self.start.elapsed().as_micros() as u64,
+            direction,
+            src: message.src.clone(),
+            dest: message.dest.clone(),
+            body: message.body.clone(),
+        };
+        let mut file = self.file.lock().unwrap();
+        // Flushed every time, since the node is usually stopped by being killed
+        let written = serde_json::to_writer(&mut *file, &entry)
+            .map_err(io::Error::from)
+            .and_then(|()| file.write_all(b"\n"))
+            .and_then(|()| file.flush());
+        if let Err(err) = written {
+            warn!(%err, "writing the journal failed");
+        }
+    }
+}
+
+/// Reads the entries of the journal at `path`
+pub fn read(path: &Path) -> io::Result<Vec<Entry>> {
+    let file = BufReader::new(File::open(path)?);
+    file.lines()
+        .map(|line| serde_json::from_str(&line?).map_err(io::Error::from))
+        .collect()
+}
+
+/// Feeds the messages `recorded` says a node received into a fresh node running `W`, and returns what that node
+/// sends. Received messages keep their recorded spacing unless `fast` is set. A reply is held back until the
+/// node has sent the request it answers, so that it finds the rpc waiting for it. Returns `None` if the node was
+/// never initialized, as there is nothing to compare without an init in the journal.
+pub fn replay<W: Workload + Send + 'static>(recorded: &[Entry], fast: bool) -> Option<Vec<Entry>> {
+    let (input_send, input_recv) = mpsc::channel();
+    let (output_send, output_recv) = mpsc::channel();
+    let transport = Channel {
+        input: input_recv,
+        output: output_send,
+    };
+    let node = thread::spawn(move || Node::<W>::init_with(transport).map(Node::run).is_some());
+
+    let start = Instant::now();
+    let mut replayed = Vec::new();
+    let mut collect = |line: String| match serde_json::from_str::<RawMessage>(&line) {
+        Ok(msg) => replayed.push(Entry {
+            at_us: start.elapsed().as_micros() as u64,
+            direction: Direction::Sent,
+            src: msg.src,
+            dest: msg.dest,
+            body: msg.body,
+        }),
+        Err(err) => warn!(line, %err, "the node wrote something that is not a message"),
+    };
+
+    let first_at = recorded.first().map_or(0, |entry| entry.at_us);
+    for entry in recorded.iter().filter(|entry| entry.direction == Direction::Received) {
+        if !fast {
+            let due = Duration::from_micros(entry.at_us - first_at);
+            thread::sleep(due.saturating_sub(start.elapsed()));
+        }
+        while let Ok(line) = output_recv.try_recv() {
+            collect(line);
+        }
+
+        if let Some(in_reply_to) = entry.in_reply_to() {
+            let deadline = Instant::now() + REPLY_WAIT;
+            let mut sent = false;
+            while !sent {
+                match output_recv.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => {
+                        sent = is_request(&line, &entry.src, in_reply_to);
+                        collect(line);
+                    }
+                    Err(_) => {
+                        warn!(
+                            src = entry.src,
+                            in_reply_to, "the node never sent the request this reply answers"
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        if input_send.send(entry.line()).is_err() {
+            warn!("the node stopped before the journal was replayed");
+            break;
+        }
+    }
+
+    drop(input_send);
+    let initialized = node.join().unwrap_or(true);
+    while let Ok(line) = output_recv.try_recv() {
+        collect(line);
+    }
+    initialized.then_some(replayed)
+}
+
+fn is_request(line: &str, dest: &str, msg_id: MsgId) -> bool {
+    serde_json::from_str::<RawMessage>(line)
+        .is_ok_and(|msg| msg.dest == dest && msg.body["msg_id"].as_u64() == Some(msg_id as u64))
+}
+
+/// A message one run sent to `dest` that the other did not
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    Missing { dest: NodeId, body: Value },
+    Extra { dest: NodeId, body: Value },
+}
+
+/// Compares what two runs sent to each destination, in order. Messages to different destinations are not
+/// ordered against each other, since the time it takes others to answer decides how they interleave.
+pub fn diff(recorded: &[Entry], replayed: &[Entry]) -> Vec<Difference> {
+    let by_dest = |entries: &[Entry]| {
+        let mut by_dest: BTreeMap<NodeId, Vec<Value>> = BTreeMap::new();
+        for entry in entries.iter().filter(|entry| entry.direction == Direction::Sent) {
+            by_dest.entry(entry.dest.clone()).or_default().push(entry.body.clone());
+        }
+        by_dest
+    };
+    let mut recorded = by_dest(recorded);
+    let mut replayed = by_dest(replayed);
+
+    let mut dests: Vec<NodeId> = recorded.keys().chain(replayed.keys()).cloned().collect();
+    dests.sort();
+    dests.dedup();
+    let mut differences = Vec::new();
+    for dest in dests {
+        let old = recorded.remove(&dest).unwrap_or_default();
+        let new = replayed.remove(&dest).unwrap_or_default();
+        for (in_old, in_new, body) in align(&old, &new) {
+            match (in_old, in_new) {
+                (true, false) => differences.push(Difference::Missing {
+                    dest: dest.clone(),
+                    body,
+                }),
+                (false, true) => differences.push(Difference::Extra {
+                    dest: dest.clone(),
+                    body,
+                }),
+                _ => {}
+            }
+        }
+    }
+    differences
+}
+
+/// Lines up two sequences along their longest common subsequence, as `(in old, in new, item)`
+fn align(old: &[Value], new: &[Value]) -> Vec<(bool, bool, Value)> {
+    // common[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut aligned = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            aligned.push((true, true, old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            aligned.push((true, false, old[i].clone()));
+            i += 1;
+        } else {
+            aligned.push((false, true, new[j].clone()));
+            j += 1;
+        }
+    }
+    aligned
+}
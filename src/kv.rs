@@ -0,0 +1,191 @@
+use serde::{de::DeserializeOwned, de::IgnoredAny, Deserialize, Serialize};
+use std::marker::PhantomData;
+
+use crate::{
+    message::{Error, ErrorCode},
+    node::NodeId,
+    rpc::RetryPolicy,
+    workloads::workload::{Body, Workload},
+};
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request<K, V> {
+    Read {
+        key: K,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    Cas {
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    },
+}
+
+#[derive(Deserialize)]
+struct ReadOk<V> {
+    value: V,
+}
+
+/// A missing key reads as `None` rather than failing
+fn read_result<V>(reply: Result<ReadOk<V>, Error>) -> Result<Option<V>, Error> {
+    match reply {
+        Ok(ReadOk { value }) => Ok(Some(value)),
+        Err(error) if error.code == ErrorCode::KeyDoesNotExist => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// A compare-and-set whose precondition did not hold reports `false` rather than failing
+fn cas_result(reply: Result<IgnoredAny, Error>) -> Result<bool, Error> {
+    match reply {
+        Ok(_) => Ok(true),
+        Err(error) if matches!(error.code, ErrorCode::PreconditionFailed | ErrorCode::KeyDoesNotExist) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+/// A client for one of Maelstrom's key-value services, mapping keys of type `K` to values of type `V`
+#[derive(Clone, Debug)]
+pub struct KvClient<K, V> {
+    service: NodeId,
+    retry: Option<RetryPolicy>,
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: Serialize, V: Serialize + DeserializeOwned> KvClient<K, V> {
+    pub fn new(service: impl Into<NodeId>) -> Self {
+        KvClient {
+            service: service.into(),
+            retry: None,
+            types: PhantomData,
+        }
+    }
+
+    /// The sequentially consistent store, `seq-kv`
+    pub fn seq() -> Self {
+        KvClient::new("seq-kv")
+    }
+
+    /// The linearizable store, `lin-kv`
+    pub fn lin() -> Self {
+        KvClient::new("lin-kv")
+    }
+
+    /// The last-write-wins store, `lww-kv`
+    pub fn lww() -> Self {
+        KvClient::new("lww-kv")
+    }
+
+    /// Resends every request according to `retry` until the service answers
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        KvClient {
+            retry: Some(retry),
+            ..self
+        }
+    }
+
+    /// Reads `key`, handing `callback` the value or `None` if the key does not exist
+    pub fn read<W: Workload>(
+        &self,
+        key: K,
+        callback: impl FnOnce(&mut W, Result<Option<V>, Error>) + Send + 'static,
+    ) -> Body<W> {
+        let request = Request::<K, V>::Read { key };
+        Body::call(self.service.clone(), &request, self.retry, move |workload, reply, _| {
+            callback(workload, read_result(reply))
+        })
+    }
+
+    /// Sets `key` to `value`
+    pub fn write<W: Workload>(
+        &self,
+        key: K,
+        value: V,
+        callback: impl FnOnce(&mut W, Result<(), Error>) + Send + 'static,
+    ) -> Body<W> {
+        let request = Request::Write { key, value };
+        Body::call(self.service.clone(), &request, self.retry, move |workload, reply, _| {
+            callback(workload, reply.map(|IgnoredAny| ()))
+        })
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`, handing `callback` whether it did. With
+    /// `create_if_not_exists` a missing key is created with `to`.
+    pub fn cas<W: Workload>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        callback: impl FnOnce(&mut W, Result<bool, Error>) + Send + 'static,
+    ) -> Body<W> {
+        let request = Request::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+        Body::call(self.service.clone(), &request, self.retry, move |workload, reply, _| {
+            callback(workload, cas_result(reply))
+        })
+    }
+}
+
+#[cfg(feature = "async")]
+mod asynchronous {
+    use serde::{de::DeserializeOwned, de::IgnoredAny, Serialize};
+    use std::future::Future;
+
+    use super::{cas_result, read_result, KvClient, ReadOk, Request};
+    use crate::{
+        async_node::{AsyncWorkload, Context},
+        message::Error,
+    };
+
+    impl<K: Serialize, V: Serialize + DeserializeOwned + Send> KvClient<K, V> {
+        /// Reads `key` through `ctx`, see [`KvClient::read`]
+        pub fn read_async<'a, W: AsyncWorkload>(
+            &self,
+            ctx: &'a Context<W>,
+            key: K,
+        ) -> impl Future<Output = Result<Option<V>, Error>> + Send + 'a {
+            let reply = ctx.call::<ReadOk<V>>(self.service.clone(), &Request::<K, V>::Read { key }, self.retry);
+            async move { read_result(reply.await) }
+        }
+
+        /// Sets `key` to `value` through `ctx`
+        pub fn write_async<'a, W: AsyncWorkload>(
+            &self,
+            ctx: &'a Context<W>,
+            key: K,
+            value: V,
+        ) -> impl Future<Output = Result<(), Error>> + Send + 'a {
+            let reply = ctx.call::<IgnoredAny>(self.service.clone(), &Request::Write { key, value }, self.retry);
+            async move { reply.await.map(|IgnoredAny| ()) }
+        }
+
+        /// Compare-and-set through `ctx`, see [`KvClient::cas`]
+        pub fn cas_async<'a, W: AsyncWorkload>(
+            &self,
+            ctx: &'a Context<W>,
+            key: K,
+            from: V,
+            to: V,
+            create_if_not_exists: bool,
+        ) -> impl Future<Output = Result<bool, Error>> + Send + 'a {
+            let request = Request::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            };
+            let reply = ctx.call::<IgnoredAny>(self.service.clone(), &request, self.retry);
+            async move { cas_result(reply.await) }
+        }
+    }
+}
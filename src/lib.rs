@@ -1,3 +1,31 @@
+#[cfg(feature = "async")]
+pub mod async_node;
+pub mod batch;
+pub mod clock;
+pub mod cluster;
+pub mod faults;
+pub mod intercept;
+pub mod journal;
+pub mod kv;
+pub mod logging;
 pub mod message;
+pub mod metrics;
 pub mod node;
+pub mod options;
+pub mod outbox;
+pub mod random;
+pub mod registry;
+pub mod rpc;
+pub mod schema;
+pub mod sim;
+pub mod tcp;
+pub mod timer;
+pub mod trace;
+pub mod transport;
 pub mod workloads;
+
+pub use dist_sys_derive::messages;
+pub use schemars;
+
+// Lets the code `messages` writes name this crate the same way inside it as outside
+extern crate self as dist_sys_challenge;
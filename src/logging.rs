@@ -0,0 +1,42 @@
+use serde_json::Value;
+use tracing::Span;
+use tracing_subscriber::EnvFilter;
+
+/// The environment variable holding the log filter, such as `debug` or `dist_sys_challenge::node=debug`
+pub const LOG_VAR: &str = "RUST_LOG";
+
+/// Logs to stderr, which Maelstrom keeps, filtered by [`LOG_VAR`] and at `info` by default. Every message a node
+/// receives or sends is logged at `debug`.
+pub fn init() {
+    let filter = EnvFilter::try_from_env(LOG_VAR).unwrap_or_else(|_| EnvFilter::new("info"));
+    // Somebody else installing a subscriber first is fine, such as a test
+    let _ = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .with_env_filter(filter)
+        .try_init();
+}
+
+/// The span everything node `id` logs is recorded in
+pub(crate) fn node_span(id: &str) -> Span {
+    tracing::info_span!("node", id)
+}
+
+pub(crate) fn received(src: &str, dest: &str, body: &Value) {
+    message("received", src, dest, body);
+}
+
+pub(crate) fn sent(src: &str, dest: &str, body: &Value) {
+    message("sent", src, dest, body);
+}
+
+fn message(direction: &str, src: &str, dest: &str, body: &Value) {
+    tracing::debug!(
+        src,
+        dest,
+        r#type = body["type"].as_str().unwrap_or_default(),
+        msg_id = body["msg_id"].as_u64(),
+        in_reply_to = body["in_reply_to"].as_u64(),
+        "{direction}"
+    );
+}
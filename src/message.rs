@@ -1,11 +1,36 @@
-use serde::{Deserialize, Serialize};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{node::NodeId, workloads::workload::Workload};
+use crate::{clock::Clocks, node::NodeId, schema, trace::TraceContext, workloads::workload::Workload};
 
 pub type MsgId = usize;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub(crate) struct Message<P: Workload> {
+/// The request and response types carried in the body of a [`Message`]
+pub trait Payload {
+    type Request: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send;
+    type Response: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send;
+}
+
+impl<W: Workload> Payload for W {
+    type Request = W::Request;
+    type Response = W::Response;
+}
+
+/// Bodies kept as plain JSON, for messages that are not in terms of a workload's types
+pub(crate) struct Raw;
+
+impl Payload for Raw {
+    type Request = serde_json::Value;
+    type Response = serde_json::Value;
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Message", bound = "P::Request: JsonSchema, P::Response: JsonSchema")]
+pub(crate) struct Message<P: Payload> {
     /// A string identifying the node this message came from
     pub src: NodeId,
 
@@ -14,11 +39,124 @@ pub(crate) struct Message<P: Workload> {
 
     /// The payload of the message
     pub body: MessageBody<P::Request, P::Response>,
+
+    /// The trace of the client request this message follows from, if it is traced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+
+    /// The clocks of a workload that keeps them, as of sending this message to another node
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<Clocks>,
+}
+
+/// Why an inbound line could not be decoded into a [`Message`]
+#[derive(Debug)]
+pub(crate) enum DecodeError {
+    /// The line is not a request, so there is nobody to answer
+    Invalid(serde_json::Error),
+
+    /// The line is a request the workload does not understand, to be answered with `error`
+    Rejected { src: NodeId, msg_id: MsgId, error: Error },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Invalid(err) => write!(f, "invalid message: {err}"),
+            DecodeError::Rejected { src, msg_id, error, .. } => write!(f, "request {msg_id} from {src}: {error}"),
+        }
+    }
+}
+
+/// A message whose body has not been decoded into any workload's types yet
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RawMessage {
+    pub src: NodeId,
+    pub dest: NodeId,
+    pub body: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clocks: Option<Clocks>,
+}
+
+impl RawMessage {
+    pub(crate) fn parse(line: &str) -> Result<Self, DecodeError> {
+        serde_json::from_str(line).map_err(DecodeError::Invalid)
+    }
+
+    /// The `msg_id` a reply to this message would refer to, if it has one
+    pub fn msg_id(&self) -> Option<MsgId> {
+        let msg_id = self.body.get("msg_id")?.as_u64()?;
+        Some(msg_id as MsgId)
+    }
+
+    /// The `msg_id` this message is a reply to, if it is one
+    pub fn in_reply_to(&self) -> Option<MsgId> {
+        let in_reply_to = self.body.get("in_reply_to")?.as_u64()?;
+        Some(in_reply_to as MsgId)
+    }
+
+    /// The body of a reply on success, or the `error` it carries
+    pub(crate) fn into_reply(self) -> Result<serde_json::Value, Error> {
+        if self.body.get("type").and_then(|kind| kind.as_str()) != Some("error") {
+            return Ok(self.body);
+        }
+        match serde_json::from_value::<Error>(self.body) {
+            Ok(error) => Err(error),
+            Err(err) => Err(Error::new(ErrorCode::MalformedRequest, err.to_string())),
+        }
+    }
+}
+
+impl<W: Payload> Message<W> {
+    /// Decodes the body of a message into the workload's types. When the message is a request the workload cannot
+    /// handle, it is rejected with `not-supported` for an unknown `type` and `malformed-request` for anything else.
+    pub(crate) fn from_raw(raw: RawMessage) -> Result<Self, DecodeError> {
+        let err = match MessageBody::<W::Request, W::Response>::deserialize(&raw.body) {
+            Ok(body) => {
+                return Ok(Message {
+                    src: raw.src,
+                    dest: raw.dest,
+                    body,
+                    trace: raw.trace,
+                    clocks: raw.clocks,
+                })
+            }
+            Err(err) => err,
+        };
+
+        let msg_id = raw.body.get("msg_id").and_then(|msg_id| msg_id.as_u64());
+        let (Some(msg_id), None) = (msg_id, raw.body.get("in_reply_to")) else {
+            return Err(DecodeError::Invalid(err));
+        };
+
+        // The untagged body hides why the request did not match, so decode it on its own to find out
+        let error = match serde_json::from_value::<W::Request>(raw.body) {
+            // serde offers no structured way to tell an unknown tag apart from other mismatches
+            Err(err) if err.to_string().starts_with("unknown variant") => {
+                Error::new(ErrorCode::NotSupported, err.to_string())
+            }
+            Err(err) => Error::new(ErrorCode::MalformedRequest, err.to_string()),
+            Ok(_) => Error::new(ErrorCode::MalformedRequest, err.to_string()),
+        };
+        Err(DecodeError::Rejected {
+            src: raw.src,
+            msg_id: msg_id as MsgId,
+            error,
+        })
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum MessageBody<Request, Response> {
+    /// Tried first so that an `error` reply is never mistaken for a workload response
+    Error {
+        in_reply_to: MsgId,
+        #[serde(flatten)]
+        error: Error,
+    },
     Request {
         msg_id: MsgId,
         #[serde(flatten)]
@@ -30,3 +168,136 @@ pub enum MessageBody<Request, Response> {
         response: Response,
     },
 }
+
+/// The error codes defined by the Maelstrom protocol. Codes without a variant of their own are kept as
+/// [`ErrorCode::Other`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    /// The requested operation could not be completed within a timeout
+    Timeout,
+    /// A client sent an RPC request to a node which does not exist
+    NodeNotFound,
+    /// The requested operation is not supported by the current implementation
+    NotSupported,
+    /// The operation definitely cannot be performed at this time
+    TemporarilyUnavailable,
+    /// The client's request did not conform to the server's expectations
+    MalformedRequest,
+    /// Indefinite failure, the operation may or may not have taken place
+    Crash,
+    /// Definite failure, the operation did not (and never will) take place
+    Abort,
+    /// The client requested an operation on a key which does not exist
+    KeyDoesNotExist,
+    /// The client requested the creation of a key which already exists
+    KeyAlreadyExists,
+    /// The requested operation expected some conditions to hold, and those conditions were not met
+    PreconditionFailed,
+    /// The requested transaction has been aborted because of a conflict with another transaction
+    TxnConflict,
+    Other(u32),
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Other(code),
+        }
+    }
+}
+
+// Written by hand since the code is serialized as a plain number
+impl JsonSchema for ErrorCode {
+    fn schema_name() -> String {
+        "ErrorCode".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            ..Default::default()
+        };
+        schema.metadata().description = Some(
+            "A Maelstrom error code: 0 timeout, 1 node-not-found, 10 not-supported, 11 temporarily-unavailable, \
+             12 malformed-request, 13 crash, 14 abort, 20 key-does-not-exist, 21 key-already-exists, \
+             22 precondition-failed, 30 txn-conflict"
+                .to_string(),
+        );
+        schema.into()
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(code) => code,
+        }
+    }
+}
+
+/// The body of an `error` reply
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error")]
+pub struct Error {
+    pub code: ErrorCode,
+
+    /// A human-readable description of what went wrong
+    #[serde(default)]
+    pub text: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Error {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl JsonSchema for Error {
+    fn schema_name() -> String {
+        "Error".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut text = gen.subschema_for::<String>().into_object();
+        text.metadata().description = Some("A human-readable description of what went wrong".to_string());
+        schema::tagged_object(
+            "error",
+            vec![("code", gen.subschema_for::<ErrorCode>()), ("text", text.into())],
+            &["code"],
+        )
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error {}: {}", u32::from(self.code), self.text)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use crate::{
+    node::NodeId,
+    options::{Flag, Kind},
+};
+
+/// The `type` of the request every node answers with its [`Metrics`], whatever its workload
+pub const REQUEST_TYPE: &str = "metrics";
+
+/// How often a running node logs a summary of its [`Metrics`], which it also does when it shuts down
+pub const METRICS_INTERVAL: Flag = Flag {
+    name: "metrics-interval",
+    description: "how often a node logs a summary of its metrics, 0 for only when it shuts down",
+    default: "10s",
+    kind: Kind::Duration,
+};
+
+/// Message counts by `type` and by the other end of the message
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Counts {
+    pub total: u64,
+    pub by_type: BTreeMap<String, u64>,
+    pub by_peer: BTreeMap<NodeId, u64>,
+    pub bytes: u64,
+}
+
+impl Counts {
+    fn record(&mut self, kind: &str, peer: &str, bytes: usize) {
+        self.total += 1;
+        *self.by_type.entry(kind.to_string()).or_default() += 1;
+        *self.by_peer.entry(peer.to_string()).or_default() += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// A histogram of durations in microseconds, with power-of-two buckets keyed by their upper bound
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Histogram {
+    pub count: u64,
+    pub total_us: u64,
+    pub max_us: u64,
+    pub buckets: BTreeMap<u64, u64>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros().min(u64::MAX as u128) as u64;
+        self.count += 1;
+        self.total_us = self.total_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+        *self.buckets.entry(us.max(1).next_power_of_two()).or_default() += 1;
+    }
+
+    pub fn mean_us(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.total_us as f64 / self.count as f64)
+    }
+}
+
+/// What a node received, sent and how long its workload took to handle requests, kept for the node's whole life
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    /// Every node of the cluster, which tells messages between nodes apart from messages to and from clients
+    nodes: HashSet<NodeId>,
+
+    /// By `src`
+    pub received: Counts,
+
+    /// By `dest`
+    pub sent: Counts,
+
+    /// Requests received from anyone but a node, that is the operations clients asked for
+    pub client_requests: u64,
+
+    /// By request `type`
+    pub handle_request: BTreeMap<String, Histogram>,
+
+    /// Gossip dropped because the outbox was full
+    pub dropped: u64,
+}
+
+impl Metrics {
+    pub fn new(nodes: HashSet<NodeId>) -> Self {
+        Metrics {
+            nodes,
+            received: Counts::default(),
+            sent: Counts::default(),
+            client_requests: 0,
+            handle_request: BTreeMap::new(),
+            dropped: 0,
+        }
+    }
+
+    pub(crate) fn is_node(&self, id: &str) -> bool {
+        self.nodes.contains(id)
+    }
+
+    pub(crate) fn record_received(&mut self, src: &str, body: &Value, bytes: usize) {
+        let kind = type_of(body);
+        self.received.record(kind, src, bytes);
+        let is_request = body["in_reply_to"].is_null() && kind != REQUEST_TYPE;
+        if is_request && !self.nodes.contains(src) {
+            self.client_requests += 1;
+        }
+    }
+
+    pub(crate) fn record_sent(&mut self, dest: &str, body: &Value, bytes: usize) {
+        self.sent.record(type_of(body), dest, bytes);
+    }
+
+    pub(crate) fn record_request(&mut self, kind: &str, duration: Duration) {
+        self.handle_request
+            .entry(kind.to_string())
+            .or_default()
+            .record(duration);
+    }
+
+    /// Messages sent to other nodes
+    pub fn sent_to_nodes(&self) -> u64 {
+        self.sent
+            .by_peer
+            .iter()
+            .filter(|(dest, _)| self.nodes.contains(*dest))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Messages this node sent to other nodes per client request it received, what the broadcast challenge
+    /// grades on
+    pub fn msgs_per_op(&self) -> Option<f64> {
+        (self.client_requests > 0).then(|| self.sent_to_nodes() as f64 / self.client_requests as f64)
+    }
+
+    /// The metrics along with the derived figures, as answered to a `metrics` request
+    pub fn report(&self) -> Value {
+        serde_json::json!({
+            "received": self.received,
+            "sent": self.sent,
+            "handle_request": self.handle_request,
+            "sent_to_nodes": self.sent_to_nodes(),
+            "client_requests": self.client_requests,
+            "msgs_per_op": self.msgs_per_op(),
+            "dropped": self.dropped,
+        })
+    }
+}
+
+fn type_of(body: &Value) -> &str {
+    body["type"].as_str().unwrap_or_default()
+}
@@ -1,46 +1,695 @@
-use serde::Deserialize;
-use std::io::Write;
-use std::sync::mpsc;
+use rand::Rng;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn, Span};
 
 use crate::{
-    message::{Message, MessageBody},
+    batch::{Batches, BATCH_WINDOW},
+    clock::{self, Stamper},
+    intercept::{Chain, Interceptor, Verdict},
+    journal::{Direction, Journal},
+    logging,
+    message::{DecodeError, Error, ErrorCode, Message, MessageBody, MsgId, Payload, Raw, RawMessage},
+    metrics::{self, Metrics, METRICS_INTERVAL},
+    options::Options,
+    outbox::{self, Backlog, Class, Queue, Stamped, OUTBOX_CAPACITY},
+    rpc::{Expired, PendingRequests},
+    tcp,
+    timer::Timers,
+    trace::{self, Exporter},
+    transport::{Stdio, Transport},
     workloads::{
         init,
-        workload::{Body, Workload},
+        workload::{decode_reply, Body, Workload},
     },
 };
 
 pub type NodeId = String;
 
-#[derive(Debug)]
+/// Everything the node's thread reacts to, in the order it happened
+pub(crate) enum Event<W: Workload> {
+    /// A line read from the input
+    Line(String),
+
+    /// An rpc ran out of attempts without a response
+    RpcTimeout(MsgId),
+
+    /// A timer registered by the workload fired
+    Timer(W::Timer),
+
+    /// The input was closed
+    InputClosed,
+}
+
 pub struct Node<W: Workload> {
     /// The id of this node
     pub id: NodeId,
 
+    /// The span everything the node logs is recorded in
+    span: Span,
+
     /// The workload this node is running
     workload: W,
+
+    /// Rpcs waiting for a response, shared with the sender thread that allocates their ids
+    pending: Arc<Mutex<PendingRequests<W>>>,
+
+    events: mpsc::Receiver<Event<W>>,
+
+    /// The node's own handle on the outbox, used to answer requests the workload failed
+    outbox: outbox::Sender<Body<W>>,
+
+    /// The thread writing the outbox, joined on shutdown so that no message is lost
+    sender: Option<thread::JoinHandle<()>>,
+
+    /// Shared with the outbox, which records what is sent
+    metrics: Arc<Mutex<Metrics>>,
+
+    /// Where every message received and sent is recorded, if anywhere
+    journal: Option<Arc<Journal>>,
+
+    /// Where the spans of the messages the node handles go, if anywhere
+    traces: Option<Exporter>,
+
+    /// The node's clocks, if the workload keeps them
+    stamper: Option<Arc<Stamper>>,
+
+    /// Shared with the outbox, so that one interceptor sees messages both ways
+    interceptors: Chain,
+
+    /// How often [`Node::run`] logs a summary of the metrics, see [`METRICS_INTERVAL`]
+    metrics_interval: Duration,
+}
+
+/// A message as it was written, for the metrics and the journal
+pub(crate) struct Sent {
+    pub(crate) message: RawMessage,
+    pub(crate) bytes: usize,
+}
+
+/// Writes `message` to `output` as a single line
+#[cfg(feature = "async")]
+pub(crate) fn send<P: Payload>(output: &mut impl Write, message: Message<P>) -> Sent {
+    send_raw(output, encode(message))
+}
+
+fn encode<P: Payload>(message: Message<P>) -> RawMessage {
+    RawMessage {
+        src: message.src,
+        dest: message.dest,
+        body: serde_json::to_value(&message.body).expect("serialize body"),
+        trace: message.trace,
+        clocks: message.clocks,
+    }
+}
+
+fn write_raw(output: &mut impl Write, msg: RawMessage) -> Sent {
+    logging::sent(&msg.src, &msg.dest, &msg.body);
+    let mut line = serde_json::to_vec(&msg).expect("serialize message");
+    line.push(b'\n');
+    output.write_all(&line).expect("write message");
+    Sent {
+        message: msg,
+        bytes: line.len(),
+    }
+}
+
+/// Decodes a line received before the node knows its id. Nothing but init can be handled at that point, so
+/// everything else is only logged.
+#[cfg(feature = "async")]
+pub(crate) fn decode_init(line: &str) -> Option<(NodeId, MsgId, init::Init)> {
+    match RawMessage::parse(line) {
+        Ok(raw) => init_request(raw),
+        Err(err) => {
+            warn!(%err, "ignoring message before init");
+            None
+        }
+    }
+}
+
+/// Like [`decode_init`], for a message already parsed
+fn init_request(raw: RawMessage) -> Option<(NodeId, MsgId, init::Init)> {
+    let src = raw.src.clone();
+    match Message::<init::InitWorkload>::from_raw(raw) {
+        Ok(Message {
+            src,
+            body: MessageBody::Request { request, msg_id },
+            ..
+        }) => Some((src, msg_id, request)),
+        Ok(_) => {
+            warn!(src, "ignoring message before init");
+            None
+        }
+        Err(err) => {
+            warn!(src, %err, "ignoring message before init");
+            None
+        }
+    }
+}
+
+/// The reply to init, which goes out before anything the workload sends
+fn init_ok(request: &init::Init, dest: NodeId, msg_id: MsgId) -> RawMessage {
+    encode(Message::<init::InitWorkload> {
+        src: request.node_id.clone(),
+        dest,
+        body: MessageBody::Response {
+            in_reply_to: msg_id,
+            response: init::Response::InitOk,
+        },
+        trace: None,
+        clocks: None,
+    })
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn acknowledge_init(output: &mut impl Write, request: &init::Init, dest: NodeId, msg_id: MsgId) -> Sent {
+    send_raw(output, init_ok(request, dest, msg_id))
+}
+
+/// Like [`send`], for a message already encoded
+fn send_raw(output: &mut impl Write, message: RawMessage) -> Sent {
+    let sent = write_raw(output, message);
+    output.flush().expect("flush output");
+    sent
+}
+
+fn reader_thread<W: Workload>(input: impl Iterator<Item = io::Result<String>>, events: mpsc::Sender<Event<W>>) {
+    for line in input {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                warn!(%err, "skipping input line");
+                continue;
+            }
+            Err(err) => {
+                error!(%err, "reading input failed");
+                break;
+            }
+        };
+        if events.send(Event::Line(line)).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::InputClosed);
+}
+
+/// Where a node talks to the rest of the cluster
+#[derive(Clone, Debug)]
+pub enum Serve {
+    /// Under Maelstrom, through stdin and stdout
+    Stdio,
+
+    /// As node `id` of the TCP cluster described in `config`, see [`tcp`]
+    Tcp { config: PathBuf, id: NodeId },
+}
+
+impl Serve {
+    /// Parses the arguments the node binaries take: none for [`Serve::Stdio`], or `--tcp <config> <node-id>`
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        match args {
+            [] => Some(Serve::Stdio),
+            [flag, config, id] if flag == "--tcp" => Some(Serve::Tcp {
+                config: PathBuf::from(config),
+                id: id.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Runs a node of `W` with `options` until its input closes, exiting the process if it fails
+pub fn serve<W: Workload + Send + 'static>(serve: Serve, options: Options) {
+    match serve {
+        Serve::Stdio => {
+            if let Some(node) = Node::<W>::builder().options(options).init() {
+                node.run();
+            }
+        }
+        Serve::Tcp { config, id } => {
+            if let Err(err) = tcp::serve::<W>(id.clone(), &config, options) {
+                error!(%err, "node {id} failed");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The entry point of a binary running a single workload. Without arguments the node runs under Maelstrom on
+/// stdio. With `--tcp <config> <node-id>` it runs as node `node-id` of the cluster described in `config`.
+pub fn main<W: Workload + Send + 'static>() {
+    logging::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(how) = Serve::from_args(&args) else {
+        eprintln!(
+            "usage: {} [--tcp <config> <node-id>]",
+            std::env::args().next().unwrap_or_default()
+        );
+        std::process::exit(2);
+    };
+    serve::<W>(how, Options::default());
+}
+
+/// Sets up a [`Node`] before it starts, see [`Node::builder`]
+pub struct Builder<W> {
+    options: Options,
+    interceptors: Chain,
+    workload: PhantomData<fn() -> W>,
+}
+
+impl<W: Workload + Send + 'static> Builder<W> {
+    /// Gives the node's flags, and those of its workload, these values instead of their defaults
+    pub fn options(self, options: Options) -> Self {
+        Builder { options, ..self }
+    }
+
+    /// Adds `interceptor` to the end of the node's chain, like [`Node::intercept`] but before the node starts, so
+    /// that it also sees init, init_ok and whatever the workload sends as it starts
+    pub fn intercept(self, interceptor: impl Interceptor + 'static) -> Self {
+        self.interceptors.push(Box::new(interceptor));
+        self
+    }
+
+    /// See [`Node::init`]
+    pub fn init(self) -> Option<Node<W>> {
+        self.init_recorded(Stdio, Journal::from_env())
+    }
+
+    /// See [`Node::init_with`]
+    pub fn init_with(self, transport: impl Transport) -> Option<Node<W>> {
+        self.init_recorded(transport, None)
+    }
+
+    /// See [`Node::init_recorded`]
+    pub fn init_recorded(self, transport: impl Transport, journal: Option<Journal>) -> Option<Node<W>> {
+        let journal = journal.map(Arc::new);
+        let (input, mut output) = transport.split();
+        let (events_send, events_recv) = mpsc::channel();
+
+        let reader_events = events_send.clone();
+        thread::spawn(move || reader_thread(input, reader_events));
+
+        let (src, msg_id, request) = loop {
+            let line = match events_recv.recv() {
+                Ok(Event::Line(line)) => line,
+                Ok(Event::RpcTimeout(_) | Event::Timer(_)) => continue,
+                Ok(Event::InputClosed) | Err(_) => {
+                    warn!("input closed before the node was initialized");
+                    return None;
+                }
+            };
+            let raw = match RawMessage::parse(&line) {
+                Ok(raw) => raw,
+                Err(err) => {
+                    warn!(%err, "ignoring message before init");
+                    continue;
+                }
+            };
+            if let Some(journal) = &journal {
+                journal.record(Direction::Received, &raw);
+            }
+            let (src, dest, msg_id, in_reply_to) = (raw.src.clone(), raw.dest.clone(), raw.msg_id(), raw.in_reply_to());
+            let raw = match self.interceptors.inbound(raw) {
+                Verdict::Pass(raw) => raw,
+                Verdict::Drop => continue,
+                Verdict::Answer(body) if !body.is_object() => {
+                    warn!(%src, %body, "dropping an answer that is not an object");
+                    continue;
+                }
+                Verdict::Answer(mut body) => {
+                    // Answered in the name of the node the message was addressed to, as its id isn't known yet
+                    if let (Some(msg_id), None) = (msg_id, in_reply_to) {
+                        body["in_reply_to"] = msg_id.into();
+                        let reply = RawMessage {
+                            src: dest,
+                            dest: src,
+                            body,
+                            trace: None,
+                            clocks: None,
+                        };
+                        let sent = send_raw(&mut output, reply);
+                        if let Some(journal) = &journal {
+                            journal.record(Direction::Sent, &sent.message);
+                        }
+                    }
+                    continue;
+                }
+            };
+            if let Some(init) = init_request(raw) {
+                break init;
+            }
+        };
+
+        // Acknowledged before the sender thread takes over the output, so init_ok comes first. It is no request,
+        // so an interceptor answering it only drops it.
+        if let Verdict::Pass(message) = self.interceptors.outbound(init_ok(&request, src, msg_id)) {
+            let sent = send_raw(&mut output, message);
+            if let Some(journal) = &journal {
+                journal.record(Direction::Sent, &sent.message);
+            }
+        }
+
+        Some(self.start(
+            request.node_id,
+            request.node_ids,
+            output,
+            events_send,
+            events_recv,
+            journal,
+        ))
+    }
+
+    /// See [`Node::start_with`]
+    pub fn start_with(self, id: NodeId, all_nodes: HashSet<NodeId>, transport: impl Transport) -> Node<W> {
+        let (input, output) = transport.split();
+        let (events_send, events_recv) = mpsc::channel();
+
+        let reader_events = events_send.clone();
+        thread::spawn(move || reader_thread(input, reader_events));
+
+        self.start(id, all_nodes, output, events_send, events_recv, None)
+    }
+
+    fn start(
+        self,
+        id: NodeId,
+        all_nodes: HashSet<NodeId>,
+        output: impl Write + Send + 'static,
+        events_send: mpsc::Sender<Event<W>>,
+        events_recv: mpsc::Receiver<Event<W>>,
+        journal: Option<Arc<Journal>>,
+    ) -> Node<W> {
+        let capacity = self.options.count(&OUTBOX_CAPACITY);
+        let (mut node, mut outbox) = Node::new(id, all_nodes, events_recv, capacity, &self.options, self.interceptors);
+        node.traces = Exporter::from_env(&node.id);
+        node.journal = journal.clone();
+        outbox.journal = journal;
+        node.sender = Some(thread::spawn(move || sender_thread(outbox, output, events_send)));
+        node
+    }
+}
+
+impl<W: Workload + Send + 'static> Node<W> {
+    /// Sets up a node that starts like [`Node::init`] and the others do, with more than their defaults
+    pub fn builder() -> Builder<W> {
+        Builder {
+            options: Options::default(),
+            interceptors: Chain::default(),
+            workload: PhantomData,
+        }
+    }
+
+    /// Waits for init on stdin and starts the node, which then talks to Maelstrom through stdin and stdout. With
+    /// [`JOURNAL_VAR`](crate::journal::JOURNAL_VAR) set, every message is recorded to the journal it names. Spans
+    /// are exported whenever [`TRACES_VAR`](crate::trace::TRACES_VAR) is set, however the node was started.
+    /// Returns `None` if stdin closes before init arrives.
+    pub fn init() -> Option<Self> {
+        Self::builder().init()
+    }
+
+    /// Like [`Node::init`], but over `transport` instead of stdio
+    pub fn init_with(transport: impl Transport) -> Option<Self> {
+        Self::builder().init_with(transport)
+    }
+
+    /// Like [`Node::init_with`], recording every message from init on to `journal`
+    pub fn init_recorded(transport: impl Transport, journal: Option<Journal>) -> Option<Self> {
+        Self::builder().init_recorded(transport, journal)
+    }
+
+    /// Starts node `id` of `all_nodes` over `transport` right away, for running outside of Maelstrom where nobody
+    /// sends init
+    pub fn start_with(id: NodeId, all_nodes: HashSet<NodeId>, transport: impl Transport) -> Self {
+        Self::builder().start_with(id, all_nodes, transport)
+    }
+
+    /// Handles input until it is closed, then waits for everything the workload sent to be written. A summary of
+    /// the metrics is logged every [`METRICS_INTERVAL`] and once more at the end.
+    pub fn run(mut self) {
+        let interval = self.metrics_interval;
+        let mut next_summary = (!interval.is_zero()).then(|| Instant::now() + interval);
+        loop {
+            let event = match next_summary {
+                Some(at) => self.events.recv_timeout(at.saturating_duration_since(Instant::now())),
+                None => self.events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(Event::InputClosed) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(event) => self.handle_event(event),
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+            // Checked after every event too, so that a busy node still logs on time
+            if next_summary.is_some_and(|at| Instant::now() >= at) {
+                summarize(&self.span, &self.metrics.lock().unwrap(), "running");
+                next_summary = Some(Instant::now() + interval);
+            }
+        }
+
+        let Node {
+            workload,
+            outbox,
+            sender,
+            metrics,
+            span,
+            ..
+        } = self;
+        // The sender thread stops once every handle on the outbox is gone and the outbox is drained
+        drop(workload);
+        drop(outbox);
+        if let Some(sender) = sender {
+            let _ = sender.join();
+        }
+
+        summarize(&span, &metrics.lock().unwrap(), "shutting down");
+    }
+}
+
+fn summarize(span: &Span, metrics: &Metrics, state: &str) {
+    span.in_scope(|| {
+        info!(
+            received = metrics.received.total,
+            sent = metrics.sent.total,
+            bytes_sent = metrics.sent.bytes,
+            msgs_per_op = metrics.msgs_per_op(),
+            metrics = %metrics.report(),
+            "{state}"
+        )
+    });
+}
+
+/// The sending side of a node: numbers and writes the messages the workload puts in its outbox, and keeps the
+/// workload's timers and the retry schedule of its rpcs
+pub(crate) struct Outbox<W: Workload> {
+    node_id: NodeId,
+    span: Span,
+    bodies: mpsc::Receiver<Stamped<Body<W>>>,
+    next_msg_id: MsgId,
+    timers: Timers<W::Timer>,
+
+    /// Rpcs waiting for a response, shared with the node that completes them
+    pending: Arc<Mutex<PendingRequests<W>>>,
+
+    metrics: Arc<Mutex<Metrics>>,
+    journal: Option<Arc<Journal>>,
+
+    /// Requests held back to be sent together, see [`BATCH_WINDOW`]
+    batches: Batches<W>,
+
+    /// Bodies taken from the channel but not yet written, so that replies to clients can go first
+    queue: Queue<W>,
+    nodes: HashSet<NodeId>,
+    backlog: Arc<Backlog>,
+    stamper: Option<Arc<Stamper>>,
+    interceptors: Chain,
+
+    /// Lines answered by an interceptor in place of a request, to be handed to the node as received
+    answers: Vec<String>,
 }
 
-fn send<T: Workload>(message: Message<T>) {
-    let mut stdout = std::io::stdout().lock();
-    serde_json::to_writer(&mut stdout, &message).expect("write message");
-    stdout.write_all(b"\n").expect("write newline");
+impl<W: Workload> Drop for Outbox<W> {
+    fn drop(&mut self) {
+        self.backlog.close();
+    }
 }
 
-fn sender_thread<W: Workload + Send + 'static>(node_id: NodeId, outbox_recv: mpsc::Receiver<Body<W>>) {
-    let mut next_msg_id = 0;
-    for body in outbox_recv.into_iter() {
+impl<W: Workload + 'static> Outbox<W> {
+    /// The next body the workload sent, if any
+    pub(crate) fn try_recv(&self) -> Option<Stamped<Body<W>>> {
+        self.bodies.try_recv().ok()
+    }
+
+    /// Takes in everything the workload sent so far, to be written in order of [`Class`]
+    fn receive(&mut self, body: Option<Stamped<Body<W>>>) {
+        for body in body
+            .into_iter()
+            .chain(std::iter::from_fn(|| self.bodies.try_recv().ok()))
+        {
+            let class = Class::of(&body.body, &self.nodes);
+            self.queue.push(class, body);
+        }
+    }
+
+    /// The body to write next, which makes room in the outbox for another
+    fn next_queued(&mut self) -> Option<Stamped<Body<W>>> {
+        let body = self.queue.pop()?;
+        if body.body.dest().is_some() {
+            self.backlog.release();
+        }
+        Some(body)
+    }
+
+    /// The earliest instant at which [`Outbox::expire`] has something to do
+    pub(crate) fn next_wakeup(&self) -> Option<Instant> {
+        [
+            self.pending.lock().unwrap().next_wakeup(),
+            self.timers.next_wakeup(),
+            self.batches.next_wakeup(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Writes `message` unless an interceptor drops or answers it
+    fn send<P: Payload>(&mut self, output: &mut impl Write, message: Message<P>) {
+        let message = encode(message);
+        let (src, dest, msg_id) = (message.src.clone(), message.dest.clone(), message.msg_id());
+        let message = match self.interceptors.outbound(message) {
+            Verdict::Pass(message) => message,
+            Verdict::Drop => return,
+            Verdict::Answer(body) if !body.is_object() => {
+                warn!(%dest, %body, "dropping an answer that is not an object");
+                return;
+            }
+            Verdict::Answer(mut body) => {
+                // The answer comes back in as if `dest` had sent it, to be handled like any other reply
+                if let Some(msg_id) = msg_id {
+                    body["in_reply_to"] = msg_id.into();
+                    let reply = json!({"src": dest, "dest": src, "body": body});
+                    self.answers.push(reply.to_string());
+                }
+                return;
+            }
+        };
+        let Sent { message, bytes } = write_raw(output, message);
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_sent(&message.dest, &message.body, bytes);
+        if let Some(journal) = &self.journal {
+            journal.record(Direction::Sent, &message);
+        }
+    }
+
+    fn next_msg_id(&mut self) -> MsgId {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        msg_id
+    }
+
+    fn send_request(&mut self, output: &mut impl Write, dest: NodeId, request: Stamped<W::Request>) {
+        let msg_id = self.next_msg_id();
+        let msg = Message::<W> {
+            src: self.node_id.clone(),
+            dest,
+            body: MessageBody::Request {
+                msg_id,
+                request: request.body,
+            },
+            trace: request.trace,
+            clocks: request.clocks,
+        };
+        self.send(output, msg);
+    }
+
+    fn send_batch(&mut self, output: &mut impl Write, dest: NodeId, requests: Vec<Stamped<W::Request>>) {
+        for request in requests {
+            self.send_request(output, dest.clone(), request);
+        }
+    }
+
+    /// Writes the message for `body` to `output`, or registers the timer it carries. With batching on, plain
+    /// requests are held back until [`Outbox::expire`] finds their window closed. Requests carry the trace they
+    /// were sent in, while replies and errors never do. Whatever goes to another node carries the clocks it was
+    /// stamped with.
+    pub(crate) fn dispatch(&mut self, body: Stamped<Body<W>>, now: Instant, output: &mut impl Write) {
+        let _entered = self.span.clone().entered();
+        let Stamped { body, trace, clocks } = body;
+        if self.batches.is_enabled() {
+            if let Body::Request { dest, request } = body {
+                let request = Stamped {
+                    body: request,
+                    trace,
+                    clocks,
+                };
+                self.batches.push(dest, request, now);
+                return;
+            }
+            // Messages to a node keep their order, so whatever is held back for it goes out first
+            if let Some(dest) = body.dest().cloned() {
+                let requests = self.batches.take(&dest);
+                self.send_batch(output, dest, requests);
+            }
+        }
+
+        let src = self.node_id.clone();
         match body {
             Body::Request { dest, request } => {
-                let msg_id = next_msg_id;
-                next_msg_id += 1;
+                let request = Stamped {
+                    body: request,
+                    trace,
+                    clocks,
+                };
+                self.send_request(output, dest, request)
+            }
+            Body::Rpc {
+                dest,
+                request,
+                retry,
+                callback,
+            } => {
+                let msg_id = self.next_msg_id();
+                // Register before sending so the response can never arrive ahead of its callback
+                let retry = retry.map(|policy| (policy, serde_json::to_value(&request).expect("serialize request")));
+                self.pending
+                    .lock()
+                    .unwrap()
+                    .insert(msg_id, dest.clone(), decode_reply(callback), retry, now);
                 let msg = Message::<W> {
-                    src: node_id.clone(),
+                    src,
                     dest,
                     body: MessageBody::Request { msg_id, request },
+                    trace,
+                    clocks,
                 };
-                send::<W>(msg);
+                self.send(output, msg);
+            }
+            Body::Call {
+                dest,
+                request,
+                retry,
+                callback,
+            } => {
+                let msg_id = self.next_msg_id();
+                let retry = retry.map(|policy| (policy, request.clone()));
+                self.pending
+                    .lock()
+                    .unwrap()
+                    .insert(msg_id, dest.clone(), callback, retry, now);
+                let msg = Message::<Raw> {
+                    src,
+                    dest,
+                    body: MessageBody::Request { msg_id, request },
+                    trace,
+                    clocks,
+                };
+                self.send(output, msg);
             }
             Body::Response {
                 dest,
@@ -48,66 +697,369 @@ fn sender_thread<W: Workload + Send + 'static>(node_id: NodeId, outbox_recv: mps
                 response,
             } => {
                 let msg = Message::<W> {
-                    src: node_id.clone(),
+                    src,
                     dest,
                     body: MessageBody::Response { in_reply_to, response },
+                    trace: None,
+                    clocks,
                 };
-                send::<W>(msg);
+                self.send(output, msg);
             }
-        };
+            Body::Error {
+                dest,
+                in_reply_to,
+                error,
+            } => {
+                let msg = Message::<W> {
+                    src,
+                    dest,
+                    body: MessageBody::Error { in_reply_to, error },
+                    trace: None,
+                    clocks,
+                };
+                self.send(output, msg);
+            }
+            Body::Reply {
+                dest,
+                in_reply_to,
+                body,
+            } => {
+                let msg = Message::<Raw> {
+                    src,
+                    dest,
+                    body: MessageBody::Response {
+                        in_reply_to,
+                        response: body,
+                    },
+                    trace: None,
+                    clocks,
+                };
+                self.send(output, msg);
+            }
+            Body::Timer { timer, schedule } => self.timers.insert(timer, schedule, now),
+        }
+    }
+
+    /// Resends the rpcs that are due at `now` and returns the timers, rpc timeouts and answers the node has to handle
+    pub(crate) fn expire(&mut self, now: Instant, rng: &mut impl Rng, output: &mut impl Write) -> Vec<Event<W>> {
+        let _entered = self.span.clone().entered();
+        for (dest, requests) in self.batches.due(now) {
+            self.send_batch(output, dest, requests);
+        }
+        let mut events: Vec<_> = self.timers.expire(now).into_iter().map(Event::Timer).collect();
+        let expired = self.pending.lock().unwrap().expire(now, rng);
+        for expired in expired {
+            match expired {
+                Expired::Resend { msg_id, dest, request } => {
+                    debug!(msg_id, dest, "resending request");
+                    // A resend is another event for the clocks, stamped now rather than when the workload sent it
+                    let clocks = self.stamper.as_ref().and_then(|stamper| stamper.stamp(&dest));
+                    let msg = Message::<Raw> {
+                        src: self.node_id.clone(),
+                        dest,
+                        body: MessageBody::Request { msg_id, request },
+                        trace: None,
+                        clocks,
+                    };
+                    self.send(output, msg);
+                }
+                Expired::TimedOut(msg_id) => {
+                    debug!(msg_id, "giving up on request");
+                    events.push(Event::RpcTimeout(msg_id))
+                }
+            }
+        }
+        events.extend(self.answers());
+        events
+    }
+
+    /// The lines interceptors answered requests with so far, for the node to handle
+    pub(crate) fn answers(&mut self) -> Vec<Event<W>> {
+        self.answers.drain(..).map(Event::Line).collect()
+    }
+
+    /// Sends everything held back, whether or not its window has closed
+    pub(crate) fn send_batches(&mut self, output: &mut impl Write) {
+        let _entered = self.span.clone().entered();
+        for (dest, requests) in self.batches.drain() {
+            self.send_batch(output, dest, requests);
+        }
     }
 }
 
-impl<W: Workload + Send + 'static> Node<W> {
-    pub fn init() -> Self {
-        let (outbox_send, outbox_recv) = mpsc::channel();
+fn sender_thread<W: Workload + 'static>(mut outbox: Outbox<W>, output: impl Write, events: mpsc::Sender<Event<W>>) {
+    let mut rng = rand::thread_rng();
+    let mut output = BufWriter::new(output);
+    loop {
+        // Wake up for the next retry deadline or timer even when the workload is not sending anything
+        let body = match outbox.next_wakeup() {
+            Some(wakeup) => match outbox
+                .bodies
+                .recv_timeout(wakeup.saturating_duration_since(Instant::now()))
+            {
+                Ok(body) => Some(body),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match outbox.bodies.recv() {
+                Ok(body) => Some(body),
+                Err(_) => break,
+            },
+        };
+        // Take in whatever else the workload sends while writing, so that a reply to a client can jump the queue
+        outbox.receive(body);
+        while let Some(body) = outbox.next_queued() {
+            outbox.dispatch(body, Instant::now(), &mut output);
+            outbox.receive(None);
+        }
 
-        let mut de = serde_json::Deserializer::from_reader(std::io::stdin().lock());
-        let msg = Message::<init::InitWorkload>::deserialize(&mut de).expect("a valid message");
+        let expired = outbox.expire(Instant::now(), &mut rng, &mut output);
+        output.flush().expect("flush output");
+        for event in expired {
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+    }
+    outbox.send_batches(&mut output);
+    output.flush().expect("flush output");
+}
 
-        let MessageBody::Request { ref request, msg_id } = msg.body else {
-            panic!("expected Request")
+impl<W: Workload + 'static> Node<W> {
+    /// Starts the workload of node `id` and returns the node along with its outbox. The node handles whatever the
+    /// caller feeds it, while `events` is only read by [`Node::run`]. Once `capacity` messages wait in the outbox,
+    /// sending waits for the sender thread to catch up, so only a node with a sender thread may pass more than 0.
+    /// `capacity` aside, the node and its workload read their flags from `options`. Everything the workload sends,
+    /// from [`Workload::new`] on, goes through `interceptors`.
+    pub(crate) fn new(
+        id: NodeId,
+        all_nodes: HashSet<NodeId>,
+        events: mpsc::Receiver<Event<W>>,
+        capacity: usize,
+        options: &Options,
+        interceptors: Chain,
+    ) -> (Self, Outbox<W>) {
+        let pending = Arc::new(Mutex::new(PendingRequests::default()));
+        let span = logging::node_span(&id);
+        let metrics = Arc::new(Mutex::new(Metrics::new(all_nodes.clone())));
+        let stamper = W::CLOCKS.then(|| Arc::new(Stamper::new(id.clone(), all_nodes.clone())));
+        let (outbox_send, outbox_recv, backlog) = outbox::bounded(capacity, metrics.clone(), stamper.clone());
+        let outbox = Outbox {
+            node_id: id.clone(),
+            span: span.clone(),
+            bodies: outbox_recv,
+            next_msg_id: 0,
+            timers: Timers::default(),
+            pending: pending.clone(),
+            metrics: metrics.clone(),
+            journal: None,
+            batches: Batches::new(options.duration(&BATCH_WINDOW)),
+            queue: Queue::default(),
+            nodes: all_nodes.clone(),
+            backlog,
+            stamper: stamper.clone(),
+            interceptors: interceptors.clone(),
+            answers: Vec::new(),
+        };
+        let workload = span.in_scope(|| {
+            clock::within(stamper.clone(), None, || {
+                W::new(id.clone(), all_nodes, outbox_send.clone(), options)
+            })
+        });
+        let node = Node {
+            id,
+            span,
+            workload,
+            pending,
+            outbox: outbox_send,
+            events,
+            sender: None,
+            metrics,
+            journal: None,
+            traces: None,
+            stamper,
+            interceptors,
+            metrics_interval: options.duration(&METRICS_INTERVAL),
         };
+        (node, outbox)
+    }
 
-        let node_id = request.node_id.clone();
-        thread::spawn(move || sender_thread(node_id.clone(), outbox_recv));
+    pub(crate) fn handle_event(&mut self, event: Event<W>) {
+        let _entered = self.span.clone().entered();
+        match event {
+            Event::Line(line) if line.trim().is_empty() => {}
+            Event::Line(line) => self.handle_line(&line),
+            Event::RpcTimeout(msg_id) => clock::within(self.stamper.clone(), None, || {
+                // The response may have won the race against the timeout, in which case there is nothing to do
+                let pending = self.pending.lock().unwrap().remove(msg_id);
+                if let Some((dest, callback)) = pending {
+                    let error = Error::new(ErrorCode::Timeout, format!("no response to request {msg_id}"));
+                    callback(&mut self.workload, Err(error), &dest, msg_id);
+                }
+            }),
+            Event::Timer(timer) => clock::within(self.stamper.clone(), None, || self.workload.handle_timer(timer)),
+            Event::InputClosed => {}
+        }
+    }
 
-        let init_response = Message::<init::InitWorkload> {
-            src: request.node_id.clone(),
-            dest: msg.src.clone(),
-            body: MessageBody::Response {
-                in_reply_to: msg_id,
-                response: init::Response::InitOk,
-            },
+    fn handle_line(&mut self, line: &str) {
+        let raw = match RawMessage::parse(line) {
+            Ok(raw) => raw,
+            Err(err) => {
+                warn!(line, %err, "ignoring message");
+                return;
+            }
+        };
+        logging::received(&raw.src, &raw.dest, &raw.body);
+        if let Some(journal) = &self.journal {
+            journal.record(Direction::Received, &raw);
+        }
+        if raw.dest != self.id {
+            warn!(src = raw.src, dest = raw.dest, "ignoring message for another node");
+            return;
+        }
+        let (src, msg_id, in_reply_to) = (raw.src.clone(), raw.msg_id(), raw.in_reply_to());
+        let raw = match self.interceptors.inbound(raw) {
+            Verdict::Pass(raw) => raw,
+            Verdict::Drop => return,
+            Verdict::Answer(body) if !body.is_object() => {
+                warn!(%src, %body, "dropping an answer that is not an object");
+                return;
+            }
+            Verdict::Answer(body) => {
+                if let (Some(msg_id), None) = (msg_id, in_reply_to) {
+                    self.outbox
+                        .send(Body::Reply {
+                            dest: src,
+                            in_reply_to: msg_id,
+                            body,
+                        })
+                        .expect("send failed");
+                }
+                return;
+            }
         };
-        send(init_response);
+        self.metrics
+            .lock()
+            .unwrap()
+            .record_received(&raw.src, &raw.body, line.len());
 
-        Node {
-            id: request.node_id.clone(),
-            workload: W::new(request.node_id.clone(), request.node_ids.clone(), outbox_send),
+        // Clocks only move past messages from other nodes, since clients keep none
+        let received = match (&self.stamper, raw.clocks.clone()) {
+            (Some(stamper), Some(stamp)) if self.metrics.lock().unwrap().is_node(&raw.src) => {
+                stamper.receive(&stamp);
+                Some(stamp)
+            }
+            _ => None,
+        };
+        let span = self.start_span(&raw);
+        let context = span.as_ref().map(|span| span.context.clone());
+        let stamper = self.stamper.clone();
+        clock::within(stamper, received, || {
+            trace::within(context, || self.handle_raw(raw, line))
+        });
+        if let (Some(traces), Some(span)) = (&self.traces, span) {
+            traces.export(span);
         }
     }
 
-    pub fn run(mut self) {
-        let deserializer = serde_json::Deserializer::from_reader(std::io::stdin());
-        for msg in deserializer.into_iter::<Message<W>>() {
-            let msg = msg.expect("a valid message");
-            if msg.dest != self.id {
-                continue;
-            };
-            match msg.body {
-                MessageBody::Request { ref request, msg_id } => {
-                    let response_factory = |response| Body::Response {
-                        dest: msg.src.clone(),
+    /// The span of handling `raw`. A message in a trace continues it, and a client request starts a new one while
+    /// spans are exported.
+    fn start_span(&self, raw: &RawMessage) -> Option<trace::Span> {
+        let from_client = raw.in_reply_to().is_none() && !self.metrics.lock().unwrap().is_node(&raw.src);
+        if raw.trace.is_none() && !(from_client && self.traces.is_some()) {
+            return None;
+        }
+        let name = raw.body["type"].as_str().unwrap_or("message");
+        let attributes = vec![("message.src", raw.src.clone()), ("message.dest", raw.dest.clone())];
+        Some(trace::Span::start(raw.trace.as_ref(), name, attributes))
+    }
+
+    fn handle_raw(&mut self, raw: RawMessage, line: &str) {
+        // Replies go to the rpc waiting for them before anything else, since only it knows which type to expect
+        if let Some(in_reply_to) = raw.in_reply_to() {
+            let pending = self.pending.lock().unwrap().remove(in_reply_to);
+            if let Some((_, callback)) = pending {
+                let src = raw.src.clone();
+                callback(&mut self.workload, raw.into_reply(), &src, in_reply_to);
+                return;
+            }
+        }
+
+        if raw.body["type"] == metrics::REQUEST_TYPE {
+            if let Some(msg_id) = raw.body["msg_id"].as_u64() {
+                self.answer_metrics(raw.src, msg_id as MsgId);
+                return;
+            }
+        }
+
+        let kind = raw.body["type"].as_str().unwrap_or_default().to_string();
+        match Message::<W>::from_raw(raw) {
+            Ok(msg) if matches!(msg.body, MessageBody::Request { .. }) => {
+                let started = Instant::now();
+                self.handle_message(msg);
+                self.metrics.lock().unwrap().record_request(&kind, started.elapsed());
+            }
+            Ok(msg) => self.handle_message(msg),
+            Err(DecodeError::Invalid(err)) => warn!(line, %err, "ignoring message"),
+            Err(DecodeError::Rejected { src, msg_id, error, .. }) => {
+                warn!(src, msg_id, %error, "rejecting request");
+                self.outbox
+                    .send(Body::Error {
+                        dest: src,
                         in_reply_to: msg_id,
-                        response,
-                    };
-                    self.workload.handle_request(request, &msg.src, response_factory);
-                }
-                MessageBody::Response { response, in_reply_to } => {
-                    self.workload.handle_response(&response, in_reply_to, &msg.src)
+                        error,
+                    })
+                    .expect("send failed");
+            }
+        }
+    }
+
+    fn answer_metrics(&self, dest: NodeId, msg_id: MsgId) {
+        let mut body = self.metrics.lock().unwrap().report();
+        body["type"] = "metrics_ok".into();
+        self.outbox
+            .send(Body::Reply {
+                dest,
+                in_reply_to: msg_id,
+                body,
+            })
+            .expect("send failed");
+    }
+
+    /// Adds `interceptor` to the end of the chain every message received and sent goes through, see [`Interceptor`].
+    /// Only messages from now on go through it, [`Builder::intercept`] adds one in time for init.
+    pub fn intercept(&self, interceptor: impl Interceptor + 'static) {
+        self.interceptors.push(Box::new(interceptor));
+    }
+
+    /// A snapshot of the node's metrics
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    fn handle_message(&mut self, msg: Message<W>) {
+        match msg.body {
+            MessageBody::Request { ref request, msg_id } => {
+                let response_factory = |response| Body::Response {
+                    dest: msg.src.clone(),
+                    in_reply_to: msg_id,
+                    response,
+                };
+                if let Err(error) = self.workload.handle_request(request, &msg.src, response_factory) {
+                    self.outbox
+                        .send(Body::Error {
+                            dest: msg.src.clone(),
+                            in_reply_to: msg_id,
+                            error,
+                        })
+                        .expect("send failed");
                 }
             }
+            MessageBody::Response { response, in_reply_to } => {
+                self.workload.handle_response(&response, in_reply_to, &msg.src)
+            }
+            MessageBody::Error { error, in_reply_to } => self.workload.handle_error(&error, in_reply_to, &msg.src),
         }
     }
 }
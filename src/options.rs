@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// What a flag takes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A duration such as `250ms` or `2s`. A bare number is taken as milliseconds.
+    Duration,
+
+    /// A whole number
+    Count,
+}
+
+/// A setting of a workload that can be changed from the command line, such as `--gossip-interval 200ms`
+#[derive(Clone, Copy, Debug)]
+pub struct Flag {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub default: &'static str,
+    pub kind: Kind,
+}
+
+impl Flag {
+    /// Checks that `value` is something this flag takes
+    pub fn check(&self, value: &str) -> Result<(), String> {
+        let checked = match self.kind {
+            Kind::Duration => parse_duration(value).map(drop),
+            Kind::Count => value.parse::<usize>().map(drop).map_err(|err| err.to_string()),
+        };
+        checked.map_err(|err| format!("invalid value {value:?} for --{}: {err}", self.name))
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self.kind {
+            Kind::Duration => "<duration>",
+            Kind::Count => "<count>",
+        };
+        write!(
+            f,
+            "--{} {value}  {} (default {})",
+            self.name, self.description, self.default
+        )
+    }
+}
+
+/// Values given to flags, by flag name, which a node hands its workload when it starts. A flag without a value
+/// reads as its default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    values: BTreeMap<&'static str, String>,
+}
+
+impl Options {
+    /// Gives `flag` the value `value`, once checked that the flag takes it
+    pub fn set(&mut self, flag: &Flag, value: &str) -> Result<(), String> {
+        flag.check(value)?;
+        self.values.insert(flag.name, value.to_string());
+        Ok(())
+    }
+
+    /// Like [`Options::set`], panicking if `flag` doesn't take `value`
+    pub fn with(mut self, flag: &Flag, value: &str) -> Self {
+        if let Err(err) = self.set(flag, value) {
+            panic!("{err}");
+        }
+        self
+    }
+
+    /// The value of `flag` as a duration
+    pub fn duration(&self, flag: &Flag) -> Duration {
+        assert_eq!(flag.kind, Kind::Duration, "--{} is not a duration", flag.name);
+        parse_duration(self.value(flag)).expect("flags are checked when set")
+    }
+
+    /// The value of `flag` as a count
+    pub fn count(&self, flag: &Flag) -> usize {
+        assert_eq!(flag.kind, Kind::Count, "--{} is not a count", flag.name);
+        self.value(flag).parse().expect("flags are checked when set")
+    }
+
+    fn value(&self, flag: &Flag) -> &str {
+        self.values.get(flag.name).map_or(flag.default, String::as_str)
+    }
+}
+
+/// Parses `250ms`, `2s` or a bare number of milliseconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, millis) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1000)
+    } else {
+        (value, 1)
+    };
+    number
+        .parse::<u64>()
+        .map(|number| Duration::from_millis(number * millis))
+        .map_err(|_| format!("expected a duration such as 250ms or 2s, got {value:?}"))
+}
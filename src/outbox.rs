@@ -0,0 +1,272 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use tracing::debug;
+
+use crate::{
+    clock::{Clocks, Stamper},
+    metrics::Metrics,
+    node::NodeId,
+    options::{Flag, Kind},
+    trace::{self, TraceContext},
+    workloads::workload::{Body, Workload},
+};
+
+/// How many messages a workload may have waiting to be written before sending holds it up
+pub const OUTBOX_CAPACITY: Flag = Flag {
+    name: "outbox-capacity",
+    description: "how many messages may wait to be written before the workload is held up, 0 for no limit",
+    default: "1024",
+    kind: Kind::Count,
+};
+
+/// What a message is for, which decides what goes first when the outbox backs up. Ordered by priority, though a
+/// message never overtakes an older one to the same destination, see [`Queue::pop`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Class {
+    /// A reply to a client, who is waiting on it
+    Reply,
+
+    /// Any other message between nodes
+    Protocol,
+
+    /// Background traffic that is repeated anyway, such as gossip. It is dropped rather than waited for when the
+    /// outbox is full, and goes out after everything else.
+    Gossip,
+}
+
+impl Class {
+    /// The class of `body`, given every node of the cluster. Timers are never written, so they don't jump ahead.
+    pub(crate) fn of<W: Workload + ?Sized>(body: &Body<W>, nodes: &HashSet<NodeId>) -> Self {
+        match body {
+            Body::Request { request, .. } | Body::Rpc { request, .. } => W::class(request),
+            Body::Response { dest, .. } | Body::Error { dest, .. } | Body::Reply { dest, .. }
+                if !nodes.contains(dest) =>
+            {
+                Class::Reply
+            }
+            _ => Class::Protocol,
+        }
+    }
+}
+
+/// A body on its way to the sender thread, along with the trace it was sent in and, for a workload that keeps
+/// clocks, the clocks sending it advanced to
+pub(crate) struct Stamped<T> {
+    pub(crate) body: T,
+    pub(crate) trace: Option<TraceContext>,
+    pub(crate) clocks: Option<Clocks>,
+}
+
+/// The workload's end of its outbox. Sending waits while the outbox is full, except for [`Class::Gossip`], which
+/// is dropped instead, and replies, which always get in.
+pub struct Sender<T> {
+    tx: Tx<T>,
+    backlog: Option<Arc<Backlog>>,
+    stamper: Option<Arc<Stamper>>,
+}
+
+enum Tx<T> {
+    Plain(mpsc::Sender<T>),
+
+    /// The node's own outbox, where each body goes along with its stamps
+    Stamped(mpsc::Sender<Stamped<T>>),
+}
+
+/// An outbox without a limit, for workloads that run inside another one
+pub fn channel<T>() -> (Sender<T>, mpsc::Receiver<T>) {
+    let (tx, rx) = mpsc::channel();
+    let sender = Sender {
+        tx: Tx::Plain(tx),
+        backlog: None,
+        stamper: None,
+    };
+    (sender, rx)
+}
+
+/// A node's outbox, holding at most `capacity` messages that are not written yet, or any number for 0. Messages to
+/// other nodes are stamped by `stamper`, if any.
+pub(crate) fn bounded<T>(
+    capacity: usize,
+    metrics: Arc<Mutex<Metrics>>,
+    stamper: Option<Arc<Stamper>>,
+) -> (Sender<T>, mpsc::Receiver<Stamped<T>>, Arc<Backlog>) {
+    let (tx, rx) = mpsc::channel();
+    let backlog = Arc::new(Backlog {
+        capacity,
+        state: Mutex::new(State::default()),
+        room: Condvar::new(),
+        metrics,
+    });
+    let sender = Sender {
+        tx: Tx::Stamped(tx),
+        backlog: Some(backlog.clone()),
+        stamper,
+    };
+    (sender, rx, backlog)
+}
+
+impl<W: Workload + ?Sized> Sender<Body<W>> {
+    /// Hands `body` to the node's sender thread, in the trace of the message being handled. Fails once the node has
+    /// shut down.
+    // Returns what `mpsc::Sender::send` does, so that workloads handle both the same way
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, body: Body<W>) -> Result<(), mpsc::SendError<Body<W>>> {
+        if let Some(backlog) = &self.backlog {
+            if let Some(dest) = body.dest() {
+                let when_full = match &body {
+                    Body::Request { request, .. } if W::class(request) == Class::Gossip => WhenFull::Drop,
+                    Body::Response { .. } | Body::Error { .. } | Body::Reply { .. } => WhenFull::Overflow,
+                    _ => WhenFull::Wait,
+                };
+                match backlog.admit(when_full) {
+                    Admission::Admitted => {}
+                    Admission::Dropped => {
+                        debug!(dest, "outbox full, dropping gossip");
+                        return Ok(());
+                    }
+                    Admission::Closed => return Err(mpsc::SendError(body)),
+                }
+            }
+        }
+        match &self.tx {
+            Tx::Plain(tx) => tx.send(body),
+            Tx::Stamped(tx) => tx
+                .send(Stamped {
+                    clocks: self.stamper.as_ref().and_then(|stamper| stamper.stamp(body.dest()?)),
+                    body,
+                    trace: trace::current(),
+                })
+                .map_err(|mpsc::SendError(traced)| mpsc::SendError(traced.body)),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let tx = match &self.tx {
+            Tx::Plain(tx) => Tx::Plain(tx.clone()),
+            Tx::Stamped(tx) => Tx::Stamped(tx.clone()),
+        };
+        Sender {
+            tx,
+            backlog: self.backlog.clone(),
+            stamper: self.stamper.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// Counts the messages sent but not yet written, shared between the workload and the sender thread
+pub(crate) struct Backlog {
+    capacity: usize,
+    state: Mutex<State>,
+    room: Condvar,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+#[derive(Default)]
+struct State {
+    queued: usize,
+    closed: bool,
+}
+
+/// What sending a message does while the outbox is full
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WhenFull {
+    /// Waits for room
+    Wait,
+
+    /// Gives up on the message, for gossip, which is repeated anyway
+    Drop,
+
+    /// Goes over capacity, for replies. There are only ever as many of them as messages received, and holding one up
+    /// would hold up the node that has to answer, when it may be the very node the outbox waits for.
+    Overflow,
+}
+
+enum Admission {
+    Admitted,
+    Dropped,
+    Closed,
+}
+
+impl Backlog {
+    fn admit(&self, when_full: WhenFull) -> Admission {
+        let mut state = self.state.lock().unwrap();
+        let full = |state: &State| self.capacity > 0 && state.queued >= self.capacity;
+        if full(&state) && when_full == WhenFull::Drop {
+            self.metrics.lock().unwrap().dropped += 1;
+            return Admission::Dropped;
+        }
+        while full(&state) && when_full == WhenFull::Wait && !state.closed {
+            state = self.room.wait(state).unwrap();
+        }
+        if state.closed {
+            return Admission::Closed;
+        }
+        state.queued += 1;
+        Admission::Admitted
+    }
+
+    /// Makes room for one more message, once one is written
+    pub(crate) fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.queued = state.queued.saturating_sub(1);
+        self.room.notify_one();
+    }
+
+    /// Fails every send from now on, once nothing is left to write the messages
+    pub(crate) fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.room.notify_all();
+    }
+}
+
+/// Messages taken from the outbox but not yet written, by [`Class`], each numbered in the order it was sent
+pub(crate) struct Queue<W: Workload> {
+    classes: [VecDeque<(u64, Stamped<Body<W>>)>; 3],
+    next_seq: u64,
+}
+
+impl<W: Workload> Queue<W> {
+    pub(crate) fn push(&mut self, class: Class, body: Stamped<Body<W>>) {
+        self.classes[class as usize].push_back((self.next_seq, body));
+        self.next_seq += 1;
+    }
+
+    /// The oldest message of the highest class, unless an older one to the same destination waits in a lower
+    /// class. That one goes first then, so that every destination gets its messages in the order they were sent,
+    /// which batching relies on.
+    pub(crate) fn pop(&mut self) -> Option<Stamped<Body<W>>> {
+        let class = self.classes.iter().position(|queued| !queued.is_empty())?;
+        let (seq, front) = self.classes[class].front()?;
+        let oldest = front.body.dest().and_then(|dest| {
+            self.classes[class + 1..]
+                .iter()
+                .enumerate()
+                .filter_map(|(lower, queued)| {
+                    let index = queued.iter().position(|(_, queued)| queued.body.dest() == Some(dest))?;
+                    Some((queued[index].0, class + 1 + lower, index))
+                })
+                .filter(|(older, ..)| older < seq)
+                .min()
+        });
+        let (class, index) = oldest.map_or((class, 0), |(_, class, index)| (class, index));
+        self.classes[class].remove(index).map(|(_, body)| body)
+    }
+}
+
+impl<W: Workload> Default for Queue<W> {
+    fn default() -> Self {
+        Queue {
+            classes: Default::default(),
+            next_seq: 0,
+        }
+    }
+}
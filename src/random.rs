@@ -0,0 +1,20 @@
+use rand::{rngs::StdRng, SeedableRng};
+use std::cell::RefCell;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Runs `f` with this thread's random number generator. Workloads draw all their randomness from it, so that a
+/// [`Simulation`](crate::sim::Simulation) can make them deterministic.
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+/// Runs `f` with `rng` standing in for this thread's random number generator
+pub(crate) fn using<T>(rng: &mut StdRng, f: impl FnOnce() -> T) -> T {
+    RNG.with(|current| std::mem::swap(&mut *current.borrow_mut(), rng));
+    let result = f();
+    RNG.with(|current| std::mem::swap(&mut *current.borrow_mut(), rng));
+    result
+}
@@ -0,0 +1,170 @@
+use schemars::JsonSchema;
+use std::fmt::Write;
+
+use crate::{
+    batch::BATCH_WINDOW,
+    journal::{self, Entry},
+    metrics::METRICS_INTERVAL,
+    node::{self, Serve},
+    options::{Flag, Options},
+    outbox::OUTBOX_CAPACITY,
+    schema::Schemas,
+    workloads::{
+        broadcast::{self, BroadcastWorkload},
+        echo::EchoWorkload,
+        g_counter::{self, GCounterWorkload},
+        generate::GenerateWorkload,
+        kafka::KafkaWorkload,
+        workload::Workload,
+    },
+};
+
+/// The environment variable naming the workload `dist-sys` runs when none is given as its first argument
+pub const WORKLOAD_VAR: &str = "DIST_SYS_WORKLOAD";
+
+/// The flags every workload takes, on top of its own
+pub const NODE_FLAGS: &[Flag] = &[BATCH_WINDOW, OUTBOX_CAPACITY, METRICS_INTERVAL];
+
+/// A workload `dist-sys` can run, by name
+pub struct Registered {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub flags: &'static [Flag],
+    serve: fn(Serve, Options),
+    replay: fn(&[Entry], bool) -> Option<Vec<Entry>>,
+    schemas: fn() -> Schemas,
+}
+
+impl Registered {
+    const fn of<W>(name: &'static str, description: &'static str, flags: &'static [Flag]) -> Self
+    where
+        W: Workload + Send + 'static,
+        W::Request: JsonSchema,
+        W::Response: JsonSchema,
+    {
+        Registered {
+            name,
+            description,
+            flags,
+            serve: node::serve::<W>,
+            replay: journal::replay::<W>,
+            schemas: Schemas::of::<W>,
+        }
+    }
+
+    /// Runs a node of the workload until its input closes
+    pub fn serve(&self, serve: Serve, options: Options) {
+        (self.serve)(serve, options)
+    }
+
+    /// Replays a journal into a node of the workload, see [`journal::replay`]
+    pub fn replay(&self, recorded: &[Entry], fast: bool) -> Option<Vec<Entry>> {
+        (self.replay)(recorded, fast)
+    }
+
+    /// The JSON Schemas of the workload's messages
+    pub fn schemas(&self) -> Schemas {
+        (self.schemas)()
+    }
+
+    /// The flag called `name`, whether the workload's own or one of [`NODE_FLAGS`]
+    pub fn flag(&self, name: &str) -> Option<&'static Flag> {
+        self.flags.iter().chain(NODE_FLAGS).find(|flag| flag.name == name)
+    }
+}
+
+/// Every workload, in the order of the challenges
+pub const WORKLOADS: &[Registered] = &[
+    Registered::of::<EchoWorkload>("echo", "echoes every request back", &[]),
+    Registered::of::<GenerateWorkload>("generate", "generates globally unique ids", &[]),
+    Registered::of::<BroadcastWorkload>(
+        "broadcast",
+        "broadcasts values to every node through gossip",
+        broadcast::FLAGS,
+    ),
+    Registered::of::<GCounterWorkload>(
+        "g_counter",
+        "a grow-only counter replicated across nodes",
+        g_counter::FLAGS,
+    ),
+    Registered::of::<KafkaWorkload>("kafka", "a replicated log with committed offsets", &[]),
+];
+
+/// The workload called `name`
+pub fn find(name: &str) -> Option<&'static Registered> {
+    WORKLOADS.iter().find(|workload| workload.name == name)
+}
+
+/// What `dist-sys` was asked to do
+pub struct Invocation {
+    pub workload: &'static Registered,
+    pub flags: Vec<(&'static Flag, String)>,
+    pub serve: Serve,
+}
+
+impl Invocation {
+    /// Parses `dist-sys`'s arguments, `[<workload>] [--<flag> <value>]... [--tcp <config> <node-id>]`. Without a
+    /// workload among the arguments, the one named by `env_workload` is run.
+    pub fn parse(args: &[String], env_workload: Option<&str>) -> Result<Self, String> {
+        let (name, mut rest) = match args.split_first() {
+            Some((name, rest)) if !name.starts_with("--") => (name.as_str(), rest),
+            _ => match env_workload {
+                Some(name) => (name, args),
+                None => return Err(format!("no workload given, either as an argument or in {WORKLOAD_VAR}")),
+            },
+        };
+        let workload = find(name).ok_or_else(|| format!("unknown workload {name:?}"))?;
+
+        let mut flags = Vec::new();
+        while let Some((arg, after)) = rest.split_first() {
+            let Some(name) = arg.strip_prefix("--").filter(|name| *name != "tcp") else {
+                break;
+            };
+            let (name, value, after) = match name.split_once('=') {
+                Some((name, value)) => (name, value, after),
+                None => match after.split_first() {
+                    Some((value, after)) => (name, value.as_str(), after),
+                    None => return Err(format!("--{name} needs a value")),
+                },
+            };
+            let flag = workload
+                .flag(name)
+                .ok_or_else(|| format!("{} has no flag --{name}", workload.name))?;
+            flag.check(value)?;
+            flags.push((flag, value.to_string()));
+            rest = after;
+        }
+
+        let serve = Serve::from_args(rest).ok_or_else(|| format!("unexpected arguments {rest:?}"))?;
+        Ok(Invocation { workload, flags, serve })
+    }
+
+    /// Runs the workload with the flags given
+    pub fn run(self) {
+        let mut options = Options::default();
+        for (flag, value) in &self.flags {
+            options.set(flag, value).expect("flags are checked when parsed");
+        }
+        self.workload.serve(self.serve, options);
+    }
+}
+
+/// How to run `dist-sys`, along with every workload and its flags
+pub fn usage() -> String {
+    let mut usage = format!(
+        "usage: dist-sys [<workload>] [--<flag> <value>]... [--tcp <config> <node-id>]\n       \
+         dist-sys schema <dir>, to write the JSON Schemas of every message to dir\n\n\
+         The workload may instead be named by {WORKLOAD_VAR}.\n\nworkloads:\n"
+    );
+    for workload in WORKLOADS {
+        let _ = writeln!(usage, "  {:<10} {}", workload.name, workload.description);
+        for flag in workload.flags {
+            let _ = writeln!(usage, "      {flag}");
+        }
+    }
+    usage.push_str("\nflags of every workload:\n");
+    for flag in NODE_FLAGS {
+        let _ = writeln!(usage, "  {flag}");
+    }
+    usage
+}
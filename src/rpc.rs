@@ -0,0 +1,180 @@
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    message::MsgId,
+    node::NodeId,
+    workloads::workload::{ReplyCallback, Workload},
+};
+
+/// How long an rpc waits for its response and how often it is resent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How long each attempt waits for a response
+    pub timeout: Duration,
+
+    /// How many times the request is sent in total, including the first attempt
+    pub max_attempts: u32,
+
+    /// Delay before the first resend, doubled after every further attempt
+    pub initial_backoff: Duration,
+
+    /// Upper bound on the delay between two attempts
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Sends the request `max_attempts` times at most, backing off from 100ms up to 2s in between
+    pub fn new(timeout: Duration, max_attempts: u32) -> Self {
+        RetryPolicy {
+            timeout,
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    /// Sends the request once and reports a timeout if no response arrives within `timeout`
+    pub fn timeout(timeout: Duration) -> Self {
+        RetryPolicy::new(timeout, 1)
+    }
+
+    pub fn with_backoff(self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// Delay before the next attempt once `attempts` attempts timed out. Up to half of the exponential delay is
+    /// randomly removed so that nodes retrying at the same time spread out.
+    pub(crate) fn backoff(&self, attempts: u32, rng: &mut impl Rng) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        let delay = self.initial_backoff.saturating_mul(factor).min(self.max_backoff);
+        delay.mul_f64(rng.gen_range(0.5..=1.0))
+    }
+}
+
+enum Phase {
+    /// The current attempt is in flight and times out at `wake_at`
+    AwaitingResponse,
+    /// The last attempt timed out and the request is resent at `wake_at`
+    AwaitingResend,
+    /// All attempts timed out and the node has been told to fail the rpc
+    Exhausted,
+}
+
+struct Retry {
+    policy: RetryPolicy,
+    request: serde_json::Value,
+    attempts: u32,
+    wake_at: Instant,
+    phase: Phase,
+}
+
+struct PendingRequest<W: Workload> {
+    dest: NodeId,
+    callback: ReplyCallback<W>,
+    retry: Option<Retry>,
+}
+
+/// What the sender has to do for an rpc whose timer fired
+pub(crate) enum Expired {
+    /// Send the request again under its original `msg_id`
+    Resend {
+        msg_id: MsgId,
+        dest: NodeId,
+        request: serde_json::Value,
+    },
+    /// Give up and fail the callback with [`ErrorCode::Timeout`](crate::message::ErrorCode::Timeout)
+    TimedOut(MsgId),
+}
+
+/// Outstanding rpcs keyed by the `msg_id` they were sent with, ordered so that they expire deterministically
+pub(crate) struct PendingRequests<W: Workload> {
+    requests: BTreeMap<MsgId, PendingRequest<W>>,
+}
+
+impl<W: Workload> Default for PendingRequests<W> {
+    fn default() -> Self {
+        PendingRequests {
+            requests: BTreeMap::new(),
+        }
+    }
+}
+
+impl<W: Workload> PendingRequests<W> {
+    /// Registers an rpc that was just sent for the first time
+    pub(crate) fn insert(
+        &mut self,
+        msg_id: MsgId,
+        dest: NodeId,
+        callback: ReplyCallback<W>,
+        retry: Option<(RetryPolicy, serde_json::Value)>,
+        now: Instant,
+    ) {
+        let retry = retry.map(|(policy, request)| Retry {
+            policy,
+            request,
+            attempts: 1,
+            wake_at: now + policy.timeout,
+            phase: Phase::AwaitingResponse,
+        });
+        self.requests.insert(msg_id, PendingRequest { dest, callback, retry });
+    }
+
+    /// Takes an rpc out of the table, either because it got its response or because it timed out. Returns the node
+    /// the request was sent to along with the callback.
+    pub(crate) fn remove(&mut self, msg_id: MsgId) -> Option<(NodeId, ReplyCallback<W>)> {
+        self.requests
+            .remove(&msg_id)
+            .map(|pending| (pending.dest, pending.callback))
+    }
+
+    /// The earliest instant at which [`PendingRequests::expire`] has something to do
+    pub(crate) fn next_wakeup(&self) -> Option<Instant> {
+        self.requests
+            .values()
+            .filter_map(|pending| pending.retry.as_ref())
+            .filter(|retry| !matches!(retry.phase, Phase::Exhausted))
+            .map(|retry| retry.wake_at)
+            .min()
+    }
+
+    /// Advances the timers of all rpcs up to `now` and returns the resends and timeouts that are due
+    pub(crate) fn expire(&mut self, now: Instant, rng: &mut impl Rng) -> Vec<Expired> {
+        let mut expired = Vec::new();
+        for (msg_id, pending) in self.requests.iter_mut() {
+            let Some(retry) = pending.retry.as_mut() else {
+                continue;
+            };
+            if retry.wake_at > now {
+                continue;
+            }
+            match retry.phase {
+                Phase::AwaitingResponse if retry.attempts >= retry.policy.max_attempts => {
+                    retry.phase = Phase::Exhausted;
+                    expired.push(Expired::TimedOut(*msg_id));
+                }
+                Phase::AwaitingResponse => {
+                    retry.phase = Phase::AwaitingResend;
+                    retry.wake_at = now + retry.policy.backoff(retry.attempts, rng);
+                }
+                Phase::AwaitingResend => {
+                    retry.attempts += 1;
+                    retry.phase = Phase::AwaitingResponse;
+                    retry.wake_at = now + retry.policy.timeout;
+                    expired.push(Expired::Resend {
+                        msg_id: *msg_id,
+                        dest: pending.dest.clone(),
+                        request: retry.request.clone(),
+                    });
+                }
+                Phase::Exhausted => {}
+            }
+        }
+        expired
+    }
+}
@@ -0,0 +1,99 @@
+use schemars::{
+    schema::{InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject},
+    schema_for, JsonSchema,
+};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    message::{Message, Raw},
+    registry::WORKLOADS,
+    workloads::{init::InitWorkload, workload::Workload},
+};
+
+/// The JSON Schemas of one workload's messages, for writing clients in other languages
+pub struct Schemas {
+    /// The body of a request, which also carries a `msg_id`
+    pub request: RootSchema,
+
+    /// The body of a response, which also carries an `in_reply_to`
+    pub response: RootSchema,
+
+    /// A whole message to or from the workload, `src` and `dest` included
+    pub message: RootSchema,
+}
+
+impl Schemas {
+    pub fn of<W>() -> Self
+    where
+        W: Workload,
+        W::Request: JsonSchema,
+        W::Response: JsonSchema,
+    {
+        Schemas {
+            request: schema_for!(W::Request),
+            response: schema_for!(W::Response),
+            message: schema_for!(Message<W>),
+        }
+    }
+}
+
+/// The envelope every message shares, whatever its body
+pub fn envelope() -> RootSchema {
+    schema_for!(Message<Raw>)
+}
+
+/// The schema of a struct serde writes with `"type": tag`, which schemars leaves out when deriving one
+pub(crate) fn tagged_object(tag: &str, properties: Vec<(&str, Schema)>, required: &[&str]) -> Schema {
+    let kind = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(vec![tag.into()]),
+        ..Default::default()
+    };
+    let mut object = ObjectValidation::default();
+    object.properties.insert("type".to_string(), kind.into());
+    object.required.insert("type".to_string());
+    for (name, schema) in properties {
+        object.properties.insert(name.to_string(), schema);
+    }
+    object.required.extend(required.iter().map(|name| name.to_string()));
+    SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        object: Some(Box::new(object)),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Writes `<workload>.request.json`, `<workload>.response.json` and `<workload>.message.json` for every workload
+/// and for init, plus `message.json` for the envelope, to `dir`. Returns the files written.
+pub fn write_all(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let all = WORKLOADS
+        .iter()
+        .map(|workload| (workload.name, workload.schemas()))
+        .chain([("init", Schemas::of::<InitWorkload>())]);
+
+    let mut written = Vec::new();
+    for (name, schemas) in all {
+        for (kind, mut schema) in [
+            ("request", schemas.request),
+            ("response", schemas.response),
+            ("message", schemas.message),
+        ] {
+            schema.schema.metadata().title = Some(format!("{name} {kind}"));
+            written.push(write(dir, &format!("{name}.{kind}.json"), &schema)?);
+        }
+    }
+    written.push(write(dir, "message.json", &envelope())?);
+    Ok(written)
+}
+
+fn write(dir: &Path, file: &str, schema: &RootSchema) -> io::Result<PathBuf> {
+    let path = dir.join(file);
+    let mut json = serde_json::to_string_pretty(schema).expect("serialize schema");
+    json.push('\n');
+    fs::write(&path, json)?;
+    Ok(path)
+}
@@ -0,0 +1,395 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::{
+    faults::{FaultChange, FaultScript, Faults},
+    intercept::{Chain, Interceptor},
+    message::MsgId,
+    metrics::Metrics,
+    node::{Event, Node, NodeId, Outbox},
+    options::Options,
+    random,
+    workloads::workload::Workload,
+};
+
+/// The environment variable [`seed_from_env`] reads a seed to replay from
+pub const SEED_VAR: &str = "SIM_SEED";
+
+/// How much virtual time [`Simulation::rpc`] waits for a reply before giving up
+const RPC_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The seed to run a simulation with: the one in [`SEED_VAR`] when it is set, so that a failing run can be
+/// replayed, and a random one otherwise. The seed is printed either way.
+pub fn seed_from_env() -> u64 {
+    let seed = match std::env::var(SEED_VAR) {
+        Ok(seed) => seed
+            .parse()
+            .unwrap_or_else(|_| panic!("{SEED_VAR} must be a number, not {seed:?}")),
+        Err(_) => rand::random(),
+    };
+    eprintln!("simulation seed: {seed} (replay with {SEED_VAR}={seed})");
+    seed
+}
+
+/// A message on its way to `dest`
+struct InFlight {
+    at: Duration,
+    /// Breaks ties between messages arriving at the same time in the order they were sent
+    seq: u64,
+    dest: NodeId,
+    line: String,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.at, self.seq).cmp(&(other.at, other.seq))
+    }
+}
+
+struct SimNode<W: Workload> {
+    node: Node<W>,
+    outbox: Outbox<W>,
+
+    /// The virtual time until which the node does nothing, see [`Simulation::pause`]
+    paused_until: Duration,
+}
+
+/// What happens next in a simulation
+enum Next {
+    Deliver,
+    Wakeup(NodeId),
+    ChangeFaults,
+}
+
+/// Runs `n` nodes of a workload as a single-threaded, deterministic event loop. Time is virtual and only advances
+/// from one event to the next, and every random choice, from message latencies to the workloads' own, comes from
+/// `seed`. Running the same requests with the same seed therefore replays a run exactly.
+///
+/// Nodes are named `n1`, `n2`... and start out initialized. Any other id can be used as a client.
+pub struct Simulation<W: Workload> {
+    seed: u64,
+    rng: StdRng,
+
+    /// Virtual time zero, so that timers and rpc deadlines can keep using [`Instant`]
+    start: Instant,
+    elapsed: Duration,
+
+    nodes: BTreeMap<NodeId, SimNode<W>>,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    next_seq: u64,
+
+    /// Bounds of the uniformly distributed delay of every message
+    latency: (Duration, Duration),
+
+    /// Faults of the network between the nodes, on top of `latency`
+    faults: Faults,
+
+    /// Scripted changes to `faults` not applied yet, by virtual time
+    script: VecDeque<(Duration, FaultChange)>,
+
+    next_client_msg_id: BTreeMap<NodeId, MsgId>,
+
+    /// Messages delivered to clients and not taken yet, as `(client, src, body)`
+    client_inbox: VecDeque<(NodeId, NodeId, Value)>,
+
+    /// Every message delivered so far with the virtual time it arrived at
+    history: Vec<(Duration, String)>,
+}
+
+impl<W: Workload + 'static> Simulation<W> {
+    pub fn new(n: usize, seed: u64) -> Self {
+        Self::new_with_options(n, seed, |_| Options::default())
+    }
+
+    /// Like [`Simulation::new`], starting each node with the options `options` gives for its id
+    pub fn new_with_options(n: usize, seed: u64, mut options: impl FnMut(&str) -> Options) -> Self {
+        let mut sim = Simulation {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            nodes: BTreeMap::new(),
+            in_flight: BinaryHeap::new(),
+            next_seq: 0,
+            latency: (Duration::from_millis(1), Duration::from_millis(5)),
+            faults: Faults::default(),
+            script: VecDeque::new(),
+            next_client_msg_id: BTreeMap::new(),
+            client_inbox: VecDeque::new(),
+            history: Vec::new(),
+        };
+
+        let ids: Vec<NodeId> = (1..=n).map(|i| format!("n{i}")).collect();
+        for id in &ids {
+            let all_nodes = ids.iter().cloned().collect();
+            // Nothing feeds the event channel, the simulation hands every event to the node itself
+            let (_, events) = mpsc::channel();
+            let options = options(id);
+            let (node, outbox) = random::using(&mut sim.rng, || {
+                Node::new(id.clone(), all_nodes, events, 0, &options, Chain::default())
+            });
+            let sim_node = SimNode {
+                node,
+                outbox,
+                paused_until: Duration::ZERO,
+            };
+            sim.nodes.insert(id.clone(), sim_node);
+            sim.flush(id);
+        }
+        sim
+    }
+
+    /// Delays every message by a duration drawn uniformly from `min..=max`
+    pub fn with_latency(self, min: Duration, max: Duration) -> Self {
+        Simulation {
+            latency: (min, max.max(min)),
+            ..self
+        }
+    }
+
+    /// The faults of the network between the nodes, which apply to messages sent from now on
+    pub fn faults(&mut self) -> &mut Faults {
+        &mut self.faults
+    }
+
+    /// Applies the changes in `script` at their times, counted in virtual time from now
+    pub fn script(&mut self, script: FaultScript) {
+        let mut steps: Vec<_> = self.script.drain(..).collect();
+        steps.extend(
+            script
+                .into_steps()
+                .into_iter()
+                .map(|(at, change)| (self.elapsed + at, change)),
+        );
+        // Stable, so that steps at the same time keep their order
+        steps.sort_by_key(|(at, _)| *at);
+        self.script = steps.into();
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The virtual time since the simulation started
+    pub fn now(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The ids of the nodes in the simulation
+    pub fn nodes(&self) -> impl Iterator<Item = &NodeId> {
+        self.nodes.keys()
+    }
+
+    /// What node `id` has received and sent so far, panicking if there is no such node
+    pub fn metrics(&self, id: &str) -> Metrics {
+        self.nodes[id].node.metrics()
+    }
+
+    /// Stops node `id` for `duration` of virtual time, as if it hung in a long pause. Messages to it wait until it
+    /// resumes, and only then does it notice its timers and rpc deadlines. Panics if there is no such node.
+    pub fn pause(&mut self, id: &str, duration: Duration) {
+        let sim_node = self.nodes.get_mut(id).expect("no such node");
+        sim_node.paused_until = sim_node.paused_until.max(self.elapsed + duration);
+    }
+
+    /// Adds `interceptor` to the chain of node `id`, panicking if there is no such node
+    pub fn intercept(&self, id: &str, interceptor: impl Interceptor + 'static) {
+        self.nodes[id].node.intercept(interceptor);
+    }
+
+    /// Every message delivered so far, as the line the node or client received and the virtual time it arrived at
+    pub fn history(&self) -> &[(Duration, String)] {
+        &self.history
+    }
+
+    /// Sends `body` from `client` to `dest` with a fresh `msg_id`, which is returned
+    pub fn send(&mut self, client: &str, dest: &str, mut body: Value) -> MsgId {
+        let next_msg_id = self.next_client_msg_id.entry(client.to_string()).or_default();
+        let msg_id = *next_msg_id;
+        *next_msg_id += 1;
+        body["msg_id"] = msg_id.into();
+        let line = json!({"src": client, "dest": dest, "body": body}).to_string();
+        self.transmit(client, dest, line);
+        msg_id
+    }
+
+    /// Sends `body` from `client` to `dest` and runs the simulation until the reply arrives, panicking if it does
+    /// not arrive within a minute of virtual time
+    pub fn rpc(&mut self, client: &str, dest: &str, body: Value) -> Value {
+        let msg_id = self.send(client, dest, body);
+        let deadline = self.elapsed + RPC_TIMEOUT;
+        loop {
+            let reply = self
+                .client_inbox
+                .iter()
+                .position(|(to, src, body)| to == client && src == dest && body["in_reply_to"] == msg_id);
+            if let Some(i) = reply {
+                return self.client_inbox.remove(i).expect("found reply").2;
+            }
+            let next = self.next_event().map(|(at, _)| at);
+            if next.is_none_or(|at| at > deadline) {
+                panic!(
+                    "{client} got no reply from {dest} to request {msg_id} (seed {})",
+                    self.seed
+                );
+            }
+            self.step();
+        }
+    }
+
+    /// Takes the oldest message delivered to `client` that was not taken by [`Simulation::rpc`], as `(src, body)`
+    pub fn recv(&mut self, client: &str) -> Option<(NodeId, Value)> {
+        let i = self.client_inbox.iter().position(|(to, ..)| to == client)?;
+        self.client_inbox.remove(i).map(|(_, src, body)| (src, body))
+    }
+
+    /// Runs every event due within `duration` of virtual time and advances the clock by it
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.elapsed + duration;
+        while self.next_event().is_some_and(|(at, _)| at <= until) {
+            self.step();
+        }
+        self.elapsed = until;
+    }
+
+    fn next_event(&self) -> Option<(Duration, Next)> {
+        let delivery = self.in_flight.peek().map(|Reverse(msg)| (msg.at, Next::Deliver));
+        // Ties between nodes are broken by id, which the map iterates in order
+        let wakeup = self
+            .nodes
+            .iter()
+            .filter_map(|(id, sim_node)| {
+                let wakeup = sim_node.outbox.next_wakeup()?.saturating_duration_since(self.start);
+                Some((wakeup.max(sim_node.paused_until), Next::Wakeup(id.clone())))
+            })
+            .min_by_key(|(at, _)| *at);
+        let change = self.script.front().map(|(at, _)| (*at, Next::ChangeFaults));
+        // Faults change before anything else happening at the same time, then messages arrive before nodes wake up
+        [change, delivery, wakeup]
+            .into_iter()
+            .flatten()
+            .min_by_key(|(at, _)| *at)
+    }
+
+    /// Runs the next event, returning false if there is none
+    pub fn step(&mut self) -> bool {
+        let Some((at, next)) = self.next_event() else {
+            return false;
+        };
+        self.elapsed = self.elapsed.max(at);
+        match next {
+            Next::Deliver => {
+                let Reverse(mut msg) = self.in_flight.pop().expect("peeked message");
+                let paused_until = self
+                    .nodes
+                    .get(&msg.dest)
+                    .map_or(Duration::ZERO, |node| node.paused_until);
+                if paused_until > self.elapsed {
+                    // Held back in the order it was sent, ahead of whatever is sent to the node later
+                    msg.at = paused_until;
+                    self.in_flight.push(Reverse(msg));
+                    return true;
+                }
+                self.history.push((self.elapsed, msg.line.clone()));
+                self.deliver(msg);
+            }
+            Next::Wakeup(id) => {
+                let now = self.start + self.elapsed;
+                let sim_node = self.nodes.get_mut(&id).expect("node");
+                let mut output = Vec::new();
+                let events = sim_node.outbox.expire(now, &mut self.rng, &mut output);
+                self.transmit_output(&output);
+                for event in events {
+                    self.handle(&id, event);
+                }
+            }
+            Next::ChangeFaults => {
+                let (_, change) = self.script.pop_front().expect("peeked change");
+                change(&mut self.faults);
+            }
+        }
+        true
+    }
+
+    fn deliver(&mut self, msg: InFlight) {
+        if self.nodes.contains_key(&msg.dest) {
+            self.handle(&msg.dest, Event::Line(msg.line));
+            return;
+        }
+        let Ok(parsed) = serde_json::from_str::<Value>(&msg.line) else {
+            return;
+        };
+        let src = parsed["src"].as_str().unwrap_or_default().to_string();
+        self.client_inbox.push_back((msg.dest, src, parsed["body"].clone()));
+    }
+
+    fn handle(&mut self, id: &NodeId, event: Event<W>) {
+        let sim_node = self.nodes.get_mut(id).expect("node");
+        random::using(&mut self.rng, || sim_node.node.handle_event(event));
+        self.flush(id);
+    }
+
+    /// Sends whatever node `id` put in its outbox
+    fn flush(&mut self, id: &NodeId) {
+        let now = self.start + self.elapsed;
+        let sim_node = self.nodes.get_mut(id).expect("node");
+        let mut output = Vec::new();
+        while let Some(body) = sim_node.outbox.try_recv() {
+            sim_node.outbox.dispatch(body, now, &mut output);
+        }
+        let answers = sim_node.outbox.answers();
+        self.transmit_output(&output);
+        for event in answers {
+            self.handle(id, event);
+        }
+    }
+
+    fn transmit_output(&mut self, output: &[u8]) {
+        for line in String::from_utf8_lossy(output).lines() {
+            let Ok(msg) = serde_json::from_str::<Value>(line) else {
+                continue;
+            };
+            let src = msg["src"].as_str().unwrap_or_default();
+            let dest = msg["dest"].as_str().unwrap_or_default();
+            self.transmit(src, dest, line.to_string());
+        }
+    }
+
+    fn transmit(&mut self, src: &str, dest: &str, line: String) {
+        let delays = if self.nodes.contains_key(src) && self.nodes.contains_key(dest) {
+            self.faults.plan(src, dest, &mut self.rng)
+        } else {
+            vec![Duration::ZERO]
+        };
+        for delay in delays {
+            let (min, max) = self.latency;
+            let latency = self.rng.gen_range(min..=max) + delay;
+            let seq = self.next_seq;
+            self.next_seq += 1;
+            self.in_flight.push(Reverse(InFlight {
+                at: self.elapsed + latency,
+                seq,
+                dest: dest.to_string(),
+                line: line.clone(),
+            }));
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::{
+    node::{Node, NodeId},
+    options::Options,
+    transport::Transport,
+    workloads::workload::Workload,
+};
+
+/// How long connecting to a peer may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a peer that could not be reached is left alone before connecting is tried again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The address every node of a cluster listens on, read from a JSON object such as
+/// `{"n1": "127.0.0.1:7001", "n2": "127.0.0.1:7002"}`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(transparent)]
+pub struct ClusterConfig {
+    pub nodes: BTreeMap<NodeId, SocketAddr>,
+}
+
+impl ClusterConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(file).map_err(io::Error::from)
+    }
+}
+
+/// Runs node `id` of the cluster described in the config file at `path` with `options` until the process is stopped
+pub fn serve<W: Workload + Send + 'static>(id: NodeId, path: &Path, options: Options) -> io::Result<()> {
+    let config = ClusterConfig::load(path)?;
+    let all_nodes = config.nodes.keys().cloned().collect();
+    let transport = Tcp::bind(id.clone(), config)?;
+    info!(addr = %transport.listener.local_addr()?, "node {id} listening");
+    Node::<W>::builder()
+        .options(options)
+        .start_with(id, all_nodes, transport)
+        .run();
+    Ok(())
+}
+
+/// A transport where the node listens on its address from a [`ClusterConfig`]. Anyone may connect and send the
+/// same JSON messages as under Maelstrom, one per line. Messages to a peer only ever go over a connection to its
+/// configured address, and messages to anyone else go back over the connection their last message came in on.
+pub struct Tcp {
+    id: NodeId,
+    config: ClusterConfig,
+    listener: TcpListener,
+}
+
+impl Tcp {
+    pub fn bind(id: NodeId, config: ClusterConfig) -> io::Result<Self> {
+        let Some(addr) = config.nodes.get(&id) else {
+            let text = format!("{id} is not in the cluster config");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, text));
+        };
+        let listener = TcpListener::bind(addr)?;
+        Ok(Tcp { id, config, listener })
+    }
+}
+
+/// The connection to write to for every id a message was received from or sent to
+type Connections = Arc<Mutex<HashMap<NodeId, TcpStream>>>;
+
+/// Where an accepted connection is remembered as the way back to whoever is on the other end
+#[derive(Clone)]
+struct Routes {
+    connections: Connections,
+    nodes: Arc<HashSet<NodeId>>,
+}
+
+/// Reads the lines of one connection into the node's input. An accepted connection is remembered under the `src` of
+/// its first line, unless that is a node of the cluster: nodes are only reached at their configured address, so
+/// that nobody can take over the route to one by claiming to be it.
+fn connection_thread(stream: TcpStream, mut routes: Option<Routes>, input: mpsc::Sender<String>) {
+    let Ok(writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if let Some(Routes { connections, nodes }) = routes.take() {
+            match sender_of(&line) {
+                Some(src) if nodes.contains(&src) => warn!(src, "not routing to a node over a connection it opened"),
+                Some(src) => {
+                    let Ok(writer) = writer.try_clone() else {
+                        break;
+                    };
+                    connections.lock().unwrap().insert(src, writer);
+                }
+                None => {}
+            }
+        }
+        if input.send(line).is_err() {
+            return;
+        }
+    }
+}
+
+fn sender_of(line: &str) -> Option<NodeId> {
+    let msg: Value = serde_json::from_str(line).ok()?;
+    Some(msg["src"].as_str()?.to_string())
+}
+
+impl Transport for Tcp {
+    type Input = std::iter::Map<mpsc::IntoIter<String>, fn(String) -> io::Result<String>>;
+    type Output = TcpOutput;
+
+    fn split(self) -> (Self::Input, Self::Output) {
+        let (input_send, input_recv) = mpsc::channel();
+        let connections = Connections::default();
+
+        let routes = Routes {
+            connections: connections.clone(),
+            nodes: Arc::new(self.config.nodes.keys().cloned().collect()),
+        };
+        let accepted_input = input_send.clone();
+        let listener = self.listener;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let routes = routes.clone();
+                        let input = accepted_input.clone();
+                        thread::spawn(move || connection_thread(stream, Some(routes), input));
+                    }
+                    Err(err) => warn!(%err, "accepting a connection failed"),
+                }
+            }
+        });
+
+        let output = TcpOutput {
+            id: self.id,
+            config: self.config,
+            buf: Vec::new(),
+            connections,
+            input: input_send,
+            unreachable: HashMap::new(),
+        };
+        (input_recv.into_iter().map(Ok), output)
+    }
+}
+
+/// The sending side of [`Tcp`], delivering every line written to it to the connection of its `dest`
+pub struct TcpOutput {
+    id: NodeId,
+    config: ClusterConfig,
+    buf: Vec<u8>,
+    connections: Connections,
+
+    /// The node's own input, for messages it sends to itself
+    input: mpsc::Sender<String>,
+
+    /// Peers that could not be reached, until when they are not tried again
+    unreachable: HashMap<NodeId, Instant>,
+}
+
+impl TcpOutput {
+    fn deliver(&mut self, line: String) {
+        let Some(dest) = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|msg| msg["dest"].as_str().map(str::to_string))
+        else {
+            warn!(line, "dropping message without a dest");
+            return;
+        };
+        if dest == self.id {
+            let _ = self.input.send(line);
+            return;
+        }
+
+        // Written to outside of the lock, so that a slow peer doesn't hold up the connections of everyone else
+        let stream = self.connections.lock().unwrap().get(&dest).map(TcpStream::try_clone);
+        if let Some(Ok(mut stream)) = stream {
+            if writeln!(stream, "{line}").is_ok() {
+                return;
+            }
+            self.connections.lock().unwrap().remove(&dest);
+        }
+
+        match self.connect(&dest) {
+            Some(mut stream) => {
+                if let Err(err) = writeln!(stream, "{line}") {
+                    warn!(dest, %err, "dropping message");
+                    self.connections.lock().unwrap().remove(&dest);
+                }
+            }
+            None => warn!(dest, "dropping message, not connected"),
+        }
+    }
+
+    /// Opens a connection to peer `dest`, which also receives whatever it sends back
+    fn connect(&mut self, dest: &NodeId) -> Option<TcpStream> {
+        let addr = self.config.nodes.get(dest)?;
+        if self.unreachable.get(dest).is_some_and(|until| Instant::now() < *until) {
+            return None;
+        }
+        let stream = match TcpStream::connect_timeout(addr, CONNECT_TIMEOUT) {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!(dest, %addr, %err, "connecting failed");
+                self.unreachable.insert(dest.clone(), Instant::now() + RECONNECT_DELAY);
+                return None;
+            }
+        };
+        self.unreachable.remove(dest);
+
+        let reader = stream.try_clone().ok()?;
+        let input = self.input.clone();
+        thread::spawn(move || connection_thread(reader, None, input));
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(dest.clone(), stream.try_clone().ok()?);
+        Some(stream)
+    }
+}
+
+impl Write for TcpOutput {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(bytes);
+        while let Some(end) = self.buf.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.deliver(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// When a timer registered through [`Body::Timer`](crate::workloads::workload::Body::Timer) fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Fire once, after the given delay
+    Once(Duration),
+
+    /// Fire repeatedly, with the given period between firings, at least [`MIN_PERIOD`]
+    Every(Duration),
+}
+
+/// The shortest period of a [`Schedule::Every`] timer. Shorter ones, zero included, are raised to it so that a
+/// periodic timer can't fire over and over without time passing.
+pub const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+impl Schedule {
+    /// The schedule with a period below [`MIN_PERIOD`] raised to it
+    pub(crate) fn clamped(self) -> Schedule {
+        match self {
+            Schedule::Every(period) if period < MIN_PERIOD => {
+                warn!(?period, "raising the period of a timer to {MIN_PERIOD:?}");
+                Schedule::Every(MIN_PERIOD)
+            }
+            schedule => schedule,
+        }
+    }
+}
+
+struct Entry<T> {
+    deadline: Instant,
+    /// Breaks ties between equal deadlines so that timers fire in the order they were registered
+    seq: u64,
+    period: Option<Duration>,
+    timer: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.seq) == (other.deadline, other.seq)
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.deadline, self.seq).cmp(&(other.deadline, other.seq))
+    }
+}
+
+/// The timers a workload registered, ordered by when they fire next
+pub(crate) struct Timers<T> {
+    queue: BinaryHeap<Reverse<Entry<T>>>,
+    next_seq: u64,
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Timers {
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+        }
+    }
+}
+
+impl<T: Clone> Timers<T> {
+    pub(crate) fn insert(&mut self, timer: T, schedule: Schedule, now: Instant) {
+        let (delay, period) = match schedule.clamped() {
+            Schedule::Once(delay) => (delay, None),
+            Schedule::Every(period) => (period, Some(period)),
+        };
+        self.push(now + delay, period, timer);
+    }
+
+    fn push(&mut self, deadline: Instant, period: Option<Duration>, timer: T) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(Reverse(Entry {
+            deadline,
+            seq,
+            period,
+            timer,
+        }));
+    }
+
+    /// The instant at which the next timer fires
+    pub(crate) fn next_wakeup(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(entry)| entry.deadline)
+    }
+
+    /// Removes the timers that are due at `now` and returns them in firing order. Periodic timers are rearmed
+    /// relative to their previous deadline so that they do not drift.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<T> {
+        let mut fired = Vec::new();
+        let mut rearmed = Vec::new();
+        while self.next_wakeup().is_some_and(|deadline| deadline <= now) {
+            let Reverse(entry) = self.queue.pop().expect("peeked entry");
+            if let Some(period) = entry.period {
+                // Skip the firings that were missed entirely rather than delivering a burst of them
+                let mut deadline = entry.deadline + period;
+                while deadline <= now {
+                    deadline += period;
+                }
+                rearmed.push((deadline, period, entry.timer.clone()));
+            }
+            fired.push(entry.timer);
+        }
+        for (deadline, period, timer) in rearmed {
+            self.push(deadline, Some(period), timer);
+        }
+        fired
+    }
+}
@@ -0,0 +1,149 @@
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+
+use crate::{node::NodeId, random};
+
+/// The environment variable naming the file every node appends its spans to, see [`Exporter`]
+pub const TRACES_VAR: &str = "NODE_TRACES";
+
+/// Where a message sits in a trace, carried in the envelope next to `src` and `dest`. Both ids are lowercase hex,
+/// 16 bytes for the trace and 8 for the span, as in W3C Trace Context and OTLP.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TraceContext {
+    /// The trace the message belongs to, started by the client request it follows from
+    pub trace_id: String,
+
+    /// The span of the handler that sent the message
+    pub span_id: String,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceContext>> = const { RefCell::new(None) };
+}
+
+/// The trace of the message this thread is handling, if it has one
+pub(crate) fn current() -> Option<TraceContext> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with `context` as the [`current`] trace, so that the requests it sends carry it
+pub(crate) fn within<T>(context: Option<TraceContext>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(context));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Drawn from [`random::with_rng`] so that ids are deterministic in a simulation
+fn random_id(bytes: usize) -> String {
+    random::with_rng(|rng| (0..bytes).map(|_| format!("{:02x}", rng.gen::<u8>())).collect())
+}
+
+/// A node handling one message
+pub(crate) struct Span {
+    pub(crate) context: TraceContext,
+    parent: Option<String>,
+    name: String,
+    start: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    /// Starts the span of a handler, in the trace of `parent` or in a new trace
+    pub(crate) fn start(parent: Option<&TraceContext>, name: &str, attributes: Vec<(&'static str, String)>) -> Self {
+        let trace_id = parent.map_or_else(|| random_id(16), |parent| parent.trace_id.clone());
+        Span {
+            context: TraceContext {
+                trace_id,
+                span_id: random_id(8),
+            },
+            parent: parent.map(|parent| parent.span_id.clone()),
+            name: name.to_string(),
+            start: SystemTime::now(),
+            attributes,
+        }
+    }
+}
+
+fn unix_nanos(at: SystemTime) -> String {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
+
+fn attributes<'a>(attributes: impl IntoIterator<Item = (&'a str, &'a str)>) -> Value {
+    attributes
+        .into_iter()
+        .map(|(key, value)| json!({"key": key, "value": {"stringValue": value}}))
+        .collect()
+}
+
+/// Appends the spans of one node to a file in OTLP JSON, one `ExportTraceServiceRequest` per line as the
+/// OpenTelemetry collector's file exporter writes them. The nodes of a cluster may share the file.
+pub struct Exporter {
+    node: NodeId,
+    file: Mutex<File>,
+}
+
+impl Exporter {
+    /// Appends the spans of `node` to the file at `path`, creating it if needed
+    pub fn create(path: &Path, node: NodeId) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Exporter {
+            node,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// The exporter to the path in [`TRACES_VAR`], if it is set. Like a journal, one that cannot be created is
+    /// logged and left out.
+    pub fn from_env(node: &NodeId) -> Option<Self> {
+        let path = std::env::var_os(TRACES_VAR)?;
+        match Exporter::create(Path::new(&path), node.clone()) {
+            Ok(exporter) => Some(exporter),
+            Err(err) => {
+                error!(path = ?path, %err, "not exporting traces");
+                None
+            }
+        }
+    }
+
+    /// Writes `span` as having ended now
+    pub(crate) fn export(&self, span: Span) {
+        let mut otlp = json!({
+            "traceId": span.context.trace_id,
+            "spanId": span.context.span_id,
+            "name": span.name,
+            // SPAN_KIND_SERVER, since every span handles a message
+            "kind": 2,
+            "startTimeUnixNano": unix_nanos(span.start),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()),
+            "attributes": attributes(span.attributes.iter().map(|(key, value)| (*key, value.as_str()))),
+        });
+        if let Some(parent) = span.parent {
+            otlp["parentSpanId"] = parent.into();
+        }
+        let request = json!({
+            "resourceSpans": [{
+                "resource": {"attributes": attributes([("service.name", self.node.as_str())])},
+                "scopeSpans": [{
+                    "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                    "spans": [otlp],
+                }],
+            }],
+        });
+        // A single write per line, so that the lines of nodes sharing the file don't interleave
+        let mut line = request.to_string();
+        line.push('\n');
+        if let Err(err) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            warn!(%err, "exporting a span failed");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{self, BufRead, BufReader, Lines, Stdin, Stdout, Write};
+use std::sync::mpsc;
+
+/// Where a node reads its input from and writes its messages to, one JSON message per line. The node reads
+/// `Input` on one thread and writes `Output` on another.
+pub trait Transport {
+    type Input: Iterator<Item = io::Result<String>> + Send + 'static;
+    type Output: Write + Send + 'static;
+
+    fn split(self) -> (Self::Input, Self::Output);
+}
+
+/// Maelstrom's transport: messages arrive on stdin and are written to stdout
+pub struct Stdio;
+
+impl Transport for Stdio {
+    type Input = Lines<BufReader<Stdin>>;
+    type Output = Stdout;
+
+    fn split(self) -> (Self::Input, Self::Output) {
+        (BufReader::new(io::stdin()).lines(), io::stdout())
+    }
+}
+
+/// A transport over any pair of byte streams, such as files or sockets
+pub struct Streams<R, W>(pub R, pub W);
+
+impl<R: BufRead + Send + 'static, W: Write + Send + 'static> Transport for Streams<R, W> {
+    type Input = Lines<R>;
+    type Output = W;
+
+    fn split(self) -> (Self::Input, Self::Output) {
+        (self.0.lines(), self.1)
+    }
+}
+
+/// An in-memory transport, receiving lines from `input` and sending every line written on `output`, converted to `T`
+pub struct Channel<T = String> {
+    pub input: mpsc::Receiver<String>,
+    pub output: mpsc::Sender<T>,
+}
+
+impl<T: From<String> + Send + 'static> Transport for Channel<T> {
+    type Input = std::iter::Map<mpsc::IntoIter<String>, fn(String) -> io::Result<String>>;
+    type Output = LineSender<T>;
+
+    fn split(self) -> (Self::Input, Self::Output) {
+        (self.input.into_iter().map(Ok), LineSender::new(self.output))
+    }
+}
+
+/// Sends every complete line written to it, without the newline, as a `T`
+pub struct LineSender<T> {
+    buf: Vec<u8>,
+    lines: mpsc::Sender<T>,
+}
+
+impl<T> LineSender<T> {
+    pub fn new(lines: mpsc::Sender<T>) -> Self {
+        LineSender { buf: Vec::new(), lines }
+    }
+}
+
+impl<T: From<String>> Write for LineSender<T> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(bytes);
+        while let Some(end) = self.buf.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).into_owned();
+            if self.lines.send(line.into()).is_err() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,64 +1,80 @@
 use rand::seq::IteratorRandom;
-use serde::{Deserialize, Serialize};
 
 use crate::workloads::workload::Workload;
-use crate::{message, node::NodeId};
-use rand::{self, thread_rng};
-use std::collections::{HashMap, HashSet};
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
-use std::thread;
-
-use super::workload::Body;
+use crate::{
+    message::{self, Error, ErrorCode},
+    messages,
+    node::NodeId,
+    options::{Flag, Kind, Options},
+    outbox::{Class, Sender},
+    random,
+    timer::Schedule,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use super::{multi::Component, workload::Body};
 
 type MsgValue = isize;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
+#[messages]
 pub enum Request {
+    #[ok]
     Topology {
-        topology: HashMap<NodeId, HashSet<NodeId>>,
+        topology: BTreeMap<NodeId, BTreeSet<NodeId>>,
     },
+    #[ok]
     Broadcast {
         #[serde(rename = "message")]
         value: MsgValue,
     },
+    #[ok(#[serde(rename = "messages")] pub values: BTreeSet<MsgValue>)]
     Read,
     Gossip {
         #[serde(rename = "messages")]
-        values: HashSet<MsgValue>,
+        values: BTreeSet<MsgValue>,
     },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum Response {
-    TopologyOk,
-    BroadcastOk,
-    ReadOk {
-        #[serde(rename = "messages")]
-        values: HashSet<MsgValue>,
-    },
+#[derive(Clone, Debug)]
+pub enum Timer {
+    Gossip,
 }
 
-struct BroadcastState {
-    tx: Sender<Body<BroadcastWorkload>>,
-    seen_values: HashSet<MsgValue>,
-    to_broadcast: HashSet<MsgValue>,
-    neighbors: HashSet<NodeId>,
-    all_nodes: HashSet<NodeId>,
-}
+/// How often values received through gossip are passed on
+pub const GOSSIP_INTERVAL: Flag = Flag {
+    name: "gossip-interval",
+    description: "how often values received through gossip are passed on",
+    default: "500ms",
+    kind: Kind::Duration,
+};
+
+/// How many nodes each round of gossip goes to
+pub const GOSSIP_FANOUT: Flag = Flag {
+    name: "gossip-fanout",
+    description: "how many nodes each round of gossip goes to",
+    default: "4",
+    kind: Kind::Count,
+};
+
+pub const FLAGS: &[Flag] = &[GOSSIP_INTERVAL, GOSSIP_FANOUT];
 
 pub struct BroadcastWorkload {
     id: NodeId,
-    state: Arc<Mutex<BroadcastState>>,
+    tx: Sender<Body<Self>>,
+    seen_values: BTreeSet<MsgValue>,
+    to_broadcast: BTreeSet<MsgValue>,
+    fanout: usize,
+    neighbors: BTreeSet<NodeId>,
+    all_nodes: BTreeSet<NodeId>,
 }
 
-impl BroadcastState {
-    fn gossip(&mut self, rng: &mut rand::rngs::ThreadRng) {
-        let values: HashSet<_> = self.to_broadcast.union(&self.seen_values).cloned().collect();
+impl BroadcastWorkload {
+    fn gossip(&mut self) {
+        let values: BTreeSet<_> = self.to_broadcast.union(&self.seen_values).cloned().collect();
 
-        for dest in self.all_nodes.iter().choose_multiple(rng, 4) {
+        let dests = random::with_rng(|rng| self.all_nodes.iter().choose_multiple(rng, self.fanout));
+        tracing::debug!(values = values.len(), ?dests, "gossiping");
+        for dest in dests {
             let request = Body::Request {
                 dest: dest.clone(),
                 request: Request::Gossip { values: values.clone() },
@@ -66,7 +82,7 @@ impl BroadcastState {
             self.tx.send(request).expect("send failed");
         }
 
-        self.seen_values.extend(self.to_broadcast.clone().into_iter());
+        self.seen_values.extend(self.to_broadcast.clone());
         self.to_broadcast.clear();
     }
 }
@@ -74,26 +90,24 @@ impl BroadcastState {
 impl Workload for BroadcastWorkload {
     type Request = Request;
     type Response = Response;
+    type Timer = Timer;
+
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, options: &Options) -> Self {
+        tx.send(Body::Timer {
+            timer: Timer::Gossip,
+            schedule: Schedule::Every(options.duration(&GOSSIP_INTERVAL)),
+        })
+        .expect("send failed");
 
-    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
-        let state = Arc::new(Mutex::new(BroadcastState {
+        BroadcastWorkload {
+            id,
             tx,
-            all_nodes,
+            all_nodes: all_nodes.into_iter().collect(),
             seen_values: Default::default(),
             to_broadcast: Default::default(),
+            fanout: options.count(&GOSSIP_FANOUT),
             neighbors: Default::default(),
-        }));
-
-        let state_gossip = state.clone();
-        thread::spawn(move || {
-            let mut rng = rand::thread_rng();
-            loop {
-                thread::sleep(std::time::Duration::from_millis(500));
-                state_gossip.lock().unwrap().gossip(&mut rng);
-            }
-        });
-
-        BroadcastWorkload { id, state }
+        }
     }
 
     fn handle_request(
@@ -101,45 +115,72 @@ impl Workload for BroadcastWorkload {
         request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        let mut state = self.state.lock().unwrap();
-        match request {
-            Request::Topology { topology } => {
-                state.neighbors.extend(topology[&self.id].clone().into_iter());
-                state.all_nodes.extend(topology.keys().cloned());
-                state
-                    .tx
-                    .send(reponse_factory(Response::TopologyOk))
-                    .expect("send failed");
+    ) -> Result<(), Error> {
+        match request.incoming(reponse_factory) {
+            Incoming::Topology { topology, reply } => {
+                let Some(neighbors) = topology.get(&self.id) else {
+                    return Err(Error::new(
+                        ErrorCode::MalformedRequest,
+                        format!("topology does not include {}", self.id),
+                    ));
+                };
+                self.neighbors.extend(neighbors.clone());
+                self.all_nodes.extend(topology.keys().cloned());
+                self.tx.send(reply.with(TopologyOk {})).expect("send failed");
             }
-            Request::Broadcast { value } => {
+            Incoming::Broadcast { value, reply } => {
                 // Only broadcast if we haven't seen this value before
-                if !state.seen_values.contains(value) {
-                    state.seen_values.insert(*value);
-                    state.gossip(&mut thread_rng());
+                if !self.seen_values.contains(value) {
+                    self.seen_values.insert(*value);
+                    self.gossip();
                 }
 
-                state
-                    .tx
-                    .send(reponse_factory(Response::BroadcastOk))
-                    .expect("send failed");
+                self.tx.send(reply.with(BroadcastOk {})).expect("send failed");
             }
-            Request::Read => {
-                state
-                    .tx
-                    .send(reponse_factory(Response::ReadOk {
-                        values: state.seen_values.clone(),
+            Incoming::Read { reply } => {
+                self.tx
+                    .send(reply.with(ReadOk {
+                        values: self.seen_values.clone(),
                     }))
                     .expect("send failed");
             }
-            Request::Gossip { values } => {
-                let unseen_values = values - &state.seen_values;
-                state.to_broadcast.extend(unseen_values.into_iter());
+            Incoming::Gossip { values } => {
+                let unseen_values = values - &self.seen_values;
+                self.to_broadcast.extend(unseen_values);
             }
         }
+        Ok(())
     }
 
-    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, _src: &NodeId) {
-        panic!("Did not expect response of type {:?}", response);
+    fn handle_timer(&mut self, timer: Timer) {
+        match timer {
+            Timer::Gossip => self.gossip(),
+        }
     }
+
+    fn class(request: &Request) -> Class {
+        match request {
+            Request::Gossip { .. } => Class::Gossip,
+            _ => Class::Protocol,
+        }
+    }
+
+    fn coalesce(queued: &mut Request, next: Request) -> Result<(), Request> {
+        match (queued, next) {
+            (Request::Gossip { values }, Request::Gossip { values: more }) => {
+                values.extend(more);
+                Ok(())
+            }
+            (_, next) => Err(next),
+        }
+    }
+
+    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, src: &NodeId) {
+        tracing::warn!(src, ?response, "unexpected response");
+    }
+}
+
+impl Component for BroadcastWorkload {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Response::TYPES;
 }
@@ -1,31 +1,32 @@
-use std::{collections::HashSet, sync::mpsc::Sender};
+use std::collections::HashSet;
 
-use serde::{Deserialize, Serialize};
+use crate::{
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    workloads::workload,
+};
 
-use crate::{message::MsgId, node::NodeId, workloads::workload};
-
-use super::workload::Body;
+use super::{multi::Component, workload::Body};
 
 pub struct EchoWorkload {
     tx: Sender<Body<Self>>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Echo {
-    echo: String,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(tag = "type", rename = "echo_ok")]
-pub struct EchoOk {
-    echo: String,
+#[messages]
+pub enum Request {
+    #[ok(pub echo: String)]
+    Echo { echo: String },
 }
 
 impl workload::Workload for EchoWorkload {
-    type Request = Echo;
-    type Response = EchoOk;
+    type Request = Request;
+    type Response = Response;
+    type Timer = ();
 
-    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
         EchoWorkload { tx }
     }
 
@@ -34,13 +35,21 @@ impl workload::Workload for EchoWorkload {
         request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        self.tx
-            .send(reponse_factory(EchoOk {
-                echo: request.echo.clone(),
-            }))
-            .expect("send failed");
+    ) -> Result<(), Error> {
+        match request.incoming(reponse_factory) {
+            Incoming::Echo { echo, reply } => {
+                self.tx
+                    .send(reply.with(EchoOk { echo: echo.clone() }))
+                    .expect("send failed");
+            }
+        }
+        Ok(())
     }
 
-    fn handle_response(&mut self, _response: &EchoOk, _in_reply_to: MsgId, _src: &NodeId) {}
+    fn handle_response(&mut self, _response: &Response, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+impl Component for EchoWorkload {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Response::TYPES;
 }
@@ -1,46 +1,70 @@
 use rand::seq::IteratorRandom;
-use serde::{Deserialize, Serialize};
 
 use crate::workloads::workload::Workload;
-use crate::{message, node::NodeId};
-use rand::{self, thread_rng};
-use std::collections::{HashMap, HashSet};
-use std::sync::mpsc::Sender;
+use crate::{
+    message::{self, Error},
+    messages,
+    node::NodeId,
+    options::{Flag, Kind, Options},
+    outbox::{Class, Sender},
+    random,
+    timer::Schedule,
+};
+use std::collections::{BTreeMap, HashSet};
 
-use super::workload::Body;
+use super::{multi::Component, workload::Body};
 
 type CounterValue = usize;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
+#[messages]
 pub enum Request {
-    Add { delta: CounterValue },
-    SyncState { state: HashMap<NodeId, CounterValue> },
+    #[ok]
+    Add {
+        delta: CounterValue,
+    },
+    SyncState {
+        state: BTreeMap<NodeId, CounterValue>,
+    },
+    #[ok(pub value: CounterValue)]
     Read,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum Response {
-    AddOk,
-    ReadOk { value: CounterValue },
+#[derive(Clone, Debug)]
+pub enum Timer {
+    Sync,
 }
 
+/// How often the counter state is pushed to other nodes, so that updates lost to a partition are repaired
+pub const SYNC_INTERVAL: Flag = Flag {
+    name: "sync-interval",
+    description: "how often the counter state is pushed to other nodes",
+    default: "500ms",
+    kind: Kind::Duration,
+};
+
+/// How many nodes the counter state is pushed to at a time
+pub const SYNC_FANOUT: Flag = Flag {
+    name: "sync-fanout",
+    description: "how many nodes the counter state is pushed to at a time",
+    default: "4",
+    kind: Kind::Count,
+};
+
+pub const FLAGS: &[Flag] = &[SYNC_INTERVAL, SYNC_FANOUT];
+
 pub struct GCounterWorkload {
     id: NodeId,
     tx: Sender<Body<Self>>,
-    node_values: HashMap<NodeId, CounterValue>,
+    node_values: BTreeMap<NodeId, CounterValue>,
+    fanout: usize,
 }
 
 impl GCounterWorkload {
     fn sync(&self) {
-        let mut rng = thread_rng();
-        for dest in self
-            .node_values
-            .keys()
-            .filter(|id| *id != &self.id)
-            .choose_multiple(&mut rng, 4)
-        {
+        let others = self.node_values.keys().filter(|id| *id != &self.id);
+        let dests = random::with_rng(|rng| others.choose_multiple(rng, self.fanout));
+        tracing::debug!(?dests, "syncing counter state");
+        for dest in dests {
             let request = Body::Request {
                 dest: dest.clone(),
                 request: Request::SyncState {
@@ -55,12 +79,20 @@ impl GCounterWorkload {
 impl Workload for GCounterWorkload {
     type Request = Request;
     type Response = Response;
+    type Timer = Timer;
+
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, options: &Options) -> Self {
+        tx.send(Body::Timer {
+            timer: Timer::Sync,
+            schedule: Schedule::Every(options.duration(&SYNC_INTERVAL)),
+        })
+        .expect("send failed");
 
-    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
         GCounterWorkload {
             id,
             tx,
             node_values: all_nodes.into_iter().map(|node_id| (node_id, 0)).collect(),
+            fanout: options.count(&SYNC_FANOUT),
         }
     }
 
@@ -69,14 +101,16 @@ impl Workload for GCounterWorkload {
         request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        match request {
-            Request::Add { delta } => {
-                self.node_values.get_mut(&self.id).map(|value| *value += delta);
+    ) -> Result<(), Error> {
+        match request.incoming(reponse_factory) {
+            Incoming::Add { delta, reply } => {
+                if let Some(value) = self.node_values.get_mut(&self.id) {
+                    *value += delta;
+                }
                 self.sync();
-                self.tx.send(reponse_factory(Response::AddOk)).expect("send failed");
+                self.tx.send(reply.with(AddOk {})).expect("send failed");
             }
-            Request::SyncState { state } => {
+            Incoming::SyncState { state } => {
                 let prev_value: CounterValue = self.node_values.values().sum();
 
                 for (node_id, new_value) in state {
@@ -95,16 +129,46 @@ impl Workload for GCounterWorkload {
                     self.sync();
                 }
             }
-            Request::Read => {
+            Incoming::Read { reply } => {
                 let value = self.node_values.values().sum();
-                self.tx
-                    .send(reponse_factory(Response::ReadOk { value }))
-                    .expect("send failed");
+                self.tx.send(reply.with(ReadOk { value })).expect("send failed");
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_timer(&mut self, timer: Timer) {
+        match timer {
+            Timer::Sync => self.sync(),
+        }
+    }
+
+    fn class(request: &Request) -> Class {
+        match request {
+            Request::SyncState { .. } => Class::Gossip,
+            _ => Class::Protocol,
+        }
+    }
+
+    fn coalesce(queued: &mut Request, next: Request) -> Result<(), Request> {
+        match (queued, next) {
+            (Request::SyncState { state }, Request::SyncState { state: newer }) => {
+                for (node_id, value) in newer {
+                    let merged = state.entry(node_id).or_default();
+                    *merged = (*merged).max(value);
+                }
+                Ok(())
             }
+            (_, next) => Err(next),
         }
     }
 
-    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, _src: &NodeId) {
-        panic!("Did not expect response of type {:?}", response);
+    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, src: &NodeId) {
+        tracing::warn!(src, ?response, "unexpected response");
     }
 }
+
+impl Component for GCounterWorkload {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Response::TYPES;
+}
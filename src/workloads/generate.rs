@@ -1,49 +1,55 @@
 use std::collections::HashSet;
-use std::sync::mpsc::Sender;
 
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use rand::Rng;
+use uuid::{Builder, Uuid};
 
 use crate::node::NodeId;
+use crate::outbox::Sender;
 use crate::workloads::workload::Workload;
-use crate::{message, node};
+use crate::{
+    message::{self, Error},
+    messages, node,
+    options::Options,
+    random,
+};
 
-use super::workload::Body;
+use super::{multi::Component, workload::Body};
 
 pub struct GenerateWorkload {
     tx: Sender<Body<Self>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(untagged, rename_all = "snake_case")]
+#[messages]
 pub enum Request {
+    #[ok(pub id: Uuid)]
     Generate,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(untagged, rename_all = "snake_case")]
-pub enum Response {
-    GenerateOk { id: Uuid },
-}
-
 impl Workload for GenerateWorkload {
     type Request = Request;
     type Response = Response;
+    type Timer = ();
 
-    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
         GenerateWorkload { tx }
     }
 
     fn handle_request(
         &mut self,
-        _request: &Self::Request,
+        request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        self.tx
-            .send(reponse_factory(Response::GenerateOk { id: Uuid::new_v4() }))
-            .expect("send failed");
+    ) -> Result<(), Error> {
+        let Incoming::Generate { reply } = request.incoming(reponse_factory);
+        let id = Builder::from_random_bytes(random::with_rng(|rng| rng.gen())).into_uuid();
+        self.tx.send(reply.with(GenerateOk { id })).expect("send failed");
+        Ok(())
     }
 
     fn handle_response(&mut self, _response: &Self::Response, _in_reply_to: message::MsgId, _src: &node::NodeId) {}
 }
+
+impl Component for GenerateWorkload {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Response::TYPES;
+}
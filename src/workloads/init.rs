@@ -1,8 +1,16 @@
-use std::{collections::HashSet, sync::mpsc::Sender};
+use std::collections::HashSet;
 
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
 
-use crate::{message::MsgId, node::NodeId, workloads::workload::Workload};
+use crate::{
+    message::{Error, MsgId},
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    schema,
+    workloads::workload::Workload,
+};
 
 use super::workload::Body;
 
@@ -11,12 +19,30 @@ pub struct InitWorkload {
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename = "init")]
 pub struct Init {
     pub node_id: NodeId,
     pub node_ids: HashSet<NodeId>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+impl JsonSchema for Init {
+    fn schema_name() -> String {
+        "Init".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        schema::tagged_object(
+            "init",
+            vec![
+                ("node_id", gen.subschema_for::<NodeId>()),
+                ("node_ids", gen.subschema_for::<HashSet<NodeId>>()),
+            ],
+            &["node_id", "node_ids"],
+        )
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Response {
     InitOk,
@@ -25,8 +51,9 @@ pub enum Response {
 impl Workload for InitWorkload {
     type Request = Init;
     type Response = Response;
+    type Timer = ();
 
-    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
         InitWorkload { tx }
     }
 
@@ -35,11 +62,12 @@ impl Workload for InitWorkload {
         _request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
+    ) -> Result<(), Error> {
         self.tx.send(reponse_factory(Response::InitOk)).expect("send failed");
+        Ok(())
     }
 
-    fn handle_response(&mut self, _response: &Self::Response, _in_reply_to: MsgId, _src: &NodeId) {
-        panic!("InitProtocol does not handle responses")
+    fn handle_response(&mut self, _response: &Self::Response, _in_reply_to: MsgId, src: &NodeId) {
+        tracing::warn!(src, "init does not expect responses")
     }
 }
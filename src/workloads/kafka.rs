@@ -1,47 +1,40 @@
-use serde::{Deserialize, Serialize};
-
 use crate::workloads::workload::Workload;
-use crate::{message, node::NodeId};
+use crate::{
+    message::{self, Error, ErrorCode},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+};
 use std::collections::{HashMap, HashSet};
-use std::sync::mpsc::Sender;
 
-use super::workload::Body;
+use super::{multi::Component, workload::Body};
 
 type Key = String;
 type MsgValue = usize;
 type Offset = usize;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
+#[messages]
 pub enum Request {
     /// Requests that a "msg" value be appended to a log identified by "key".
+    #[ok(pub offset: Offset)]
     Send { key: Key, msg: MsgValue },
 
     /// Requests that a node return messages from a set of logs starting from the given offset in each log
+    #[ok(pub msgs: HashMap<Key, Vec<(Offset, MsgValue)>>)]
     Poll { offsets: HashMap<Key, Offset> },
 
-    /// Informs the node that messages have been successfully processed up to and including the given offset
+    /// Informs the node that messages have been successfully processed up to and including the given offset.
+    /// Fails with `key-does-not-exist` for a log nothing was sent to and with `precondition-failed` for an offset
+    /// past the end of its log or behind the one already committed, in which case nothing is committed.
+    #[ok]
     CommitOffsets { offsets: HashMap<Key, Offset> },
 
     /// Requests a map of committed offsets for a given set of logs
+    #[ok(pub offsets: HashMap<Key, Offset>)]
     ListCommittedOffsets { keys: Vec<Key> },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum Response {
-    SendOk {
-        offset: Offset,
-    },
-    PollOk {
-        msgs: HashMap<Key, Vec<(Offset, MsgValue)>>,
-    },
-    CommitOffsetsOk,
-    ListCommittedOffsetsOk {
-        offsets: HashMap<Key, Offset>,
-    },
-}
-
 #[derive(Default, Debug)]
 struct Logs {
     commit_offset: Option<Offset>,
@@ -58,8 +51,9 @@ pub struct KafkaWorkload {
 impl Workload for KafkaWorkload {
     type Request = Request;
     type Response = Response;
+    type Timer = ();
 
-    fn new(id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
+    fn new(id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
         KafkaWorkload {
             _id: id,
             tx,
@@ -72,17 +66,15 @@ impl Workload for KafkaWorkload {
         request: &Self::Request,
         _src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        match request {
-            Request::Send { key, msg } => {
+    ) -> Result<(), Error> {
+        match request.incoming(reponse_factory) {
+            Incoming::Send { key, msg, reply } => {
                 let log_entries = &mut self.logs.entry(key.clone()).or_default().entries;
                 let offset = log_entries.len();
-                log_entries.push(Some(msg.clone()));
-                self.tx
-                    .send(reponse_factory(Response::SendOk { offset }))
-                    .expect("send failed");
+                log_entries.push(Some(*msg));
+                self.tx.send(reply.with(SendOk { offset })).expect("send failed");
             }
-            Request::Poll { offsets } => {
+            Incoming::Poll { offsets, reply } => {
                 let msgs = offsets
                     .iter()
                     .filter_map(|(key, offset)| {
@@ -92,7 +84,7 @@ impl Workload for KafkaWorkload {
                             .map(|entries| {
                                 let key = key.clone();
                                 let entries_filtered = entries
-                                    .into_iter()
+                                    .iter()
                                     .enumerate()
                                     .filter_map(|(i, msg)| msg.map(|msg| (offset + i, msg)))
                                     .collect::<Vec<_>>();
@@ -100,23 +92,35 @@ impl Workload for KafkaWorkload {
                             })
                     })
                     .collect::<HashMap<_, _>>();
-                self.tx
-                    .send(reponse_factory(Response::PollOk { msgs }))
-                    .expect("send failed");
+                self.tx.send(reply.with(PollOk { msgs })).expect("send failed");
             }
-            Request::CommitOffsets { offsets } => {
+            Incoming::CommitOffsets { offsets, reply } => {
+                // Validate every offset up front so that a rejected request commits nothing
                 for (key, offset) in offsets {
-                    let log = self.logs.entry(key.clone()).or_default();
-                    log.commit_offset = Some(*offset);
-                    if log.entries.len() < *offset {
-                        log.entries.resize(*offset, None);
+                    let Some(log) = self.logs.get(key) else {
+                        return Err(Error::new(ErrorCode::KeyDoesNotExist, format!("no log for key {key}")));
+                    };
+                    if *offset >= log.entries.len() {
+                        return Err(Error::new(
+                            ErrorCode::PreconditionFailed,
+                            format!("offset {offset} is past the end of log {key}"),
+                        ));
+                    }
+                    if let Some(committed) = log.commit_offset.filter(|committed| offset < committed) {
+                        return Err(Error::new(
+                            ErrorCode::PreconditionFailed,
+                            format!("offset {offset} is behind offset {committed} already committed for log {key}"),
+                        ));
                     }
                 }
-                self.tx
-                    .send(reponse_factory(Response::CommitOffsetsOk))
-                    .expect("send failed");
+                for (key, offset) in offsets {
+                    if let Some(log) = self.logs.get_mut(key) {
+                        log.commit_offset = Some(*offset);
+                    }
+                }
+                self.tx.send(reply.with(CommitOffsetsOk {})).expect("send failed");
             }
-            Request::ListCommittedOffsets { keys } => {
+            Incoming::ListCommittedOffsets { keys, reply } => {
                 let offsets = keys
                     .iter()
                     .filter_map(|key| {
@@ -126,13 +130,19 @@ impl Workload for KafkaWorkload {
                     })
                     .collect::<HashMap<_, _>>();
                 self.tx
-                    .send(reponse_factory(Response::ListCommittedOffsetsOk { offsets }))
+                    .send(reply.with(ListCommittedOffsetsOk { offsets }))
                     .expect("send failed");
             }
         }
+        Ok(())
     }
 
-    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, _src: &NodeId) {
-        panic!("Did not expect response of type {:?}", response);
+    fn handle_response(&mut self, response: &Response, _in_reply_to: message::MsgId, src: &NodeId) {
+        tracing::warn!(src, ?response, "unexpected response");
     }
 }
+
+impl Component for KafkaWorkload {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Response::TYPES;
+}
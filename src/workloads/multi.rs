@@ -1,82 +1,466 @@
-use std::collections::HashSet;
-use std::sync::mpsc::{channel, Sender};
-use std::thread;
+use std::any::{type_name, Any};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
 
-use serde::{Deserialize, Serialize};
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
 use crate::{
-    message::MsgId,
+    message::{Error, ErrorCode, MsgId},
     node::NodeId,
+    options::Options,
+    outbox::{channel, Class, Sender},
     workloads::workload::{Body, Workload},
 };
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-#[serde(untagged)]
-pub enum Either<A, B> {
-    A(A),
-    B(B),
-}
-
-pub struct MultiWorkload<P1, P2> {
-    pub(crate) workload_1: P1,
-    pub(crate) workload_2: P2,
-}
-
-impl<P1: Workload + 'static, P2: Workload + 'static> Workload for MultiWorkload<P1, P2> {
-    type Request = Either<P1::Request, P2::Request>;
-    type Response = Either<P1::Response, P2::Response>;
-
-    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self {
-        let (send_a, recv_a) = channel();
-        let tx_a = tx.clone();
-        thread::spawn(move || {
-            for body in recv_a {
-                let wrapped_body = match body {
-                    Body::Request { dest, request } => Body::Request {
-                        dest,
-                        request: Either::A(request),
-                    },
-                    Body::Response {
-                        dest,
-                        in_reply_to,
-                        response,
-                    } => Body::Response {
-                        dest,
-                        in_reply_to,
-                        response: Either::A(response),
-                    },
-                };
-                tx_a.send(wrapped_body).expect("send failed");
+/// A workload that can run alongside others in a [`Composite`], which hands it the messages whose `type` it claims
+pub trait Component: Workload + Send + 'static {
+    /// The `type` of every request [`Workload::handle_request`] handles
+    const REQUEST_TYPES: &'static [&'static str];
+
+    /// The `type` of every response [`Workload::handle_response`] handles. A request whose `type` with `_ok`
+    /// appended is among them is answered, so its reply or `error` goes back to this component.
+    const RESPONSE_TYPES: &'static [&'static str] = &[];
+}
+
+/// Two components claiming the same message `type`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub kind: &'static str,
+    pub first: &'static str,
+    pub second: &'static str,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message type {:?} is claimed by both {} and {}",
+            self.kind, self.first, self.second
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// A request body of any `type`, left as JSON until the component claiming the type decodes it. Replies are never
+/// taken for requests, even when they carry a `msg_id` of their own.
+#[derive(Clone, Debug, Serialize)]
+#[serde(transparent)]
+pub struct Tagged(pub Value);
+
+impl Tagged {
+    pub fn kind(&self) -> &str {
+        self.0["type"].as_str().unwrap_or_default()
+    }
+}
+
+impl<'de> Deserialize<'de> for Tagged {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let body = Value::deserialize(deserializer)?;
+        if !body["type"].is_string() {
+            return Err(de::Error::missing_field("type"));
+        }
+        if body.get("in_reply_to").is_some() {
+            return Err(de::Error::custom("a reply is not a request"));
+        }
+        Ok(Tagged(body))
+    }
+}
+
+/// A timer of one of the components
+pub struct Timer {
+    component: usize,
+    timer: Box<dyn AnyTimer>,
+}
+
+impl Clone for Timer {
+    fn clone(&self) -> Self {
+        Timer {
+            component: self.component,
+            timer: self.timer.clone_box(),
+        }
+    }
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Timer")
+            .field(&self.component)
+            .field(&self.timer)
+            .finish()
+    }
+}
+
+trait AnyTimer: fmt::Debug + Send {
+    fn clone_box(&self) -> Box<dyn AnyTimer>;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + fmt::Debug + Send + 'static> AnyTimer for T {
+    fn clone_box(&self) -> Box<dyn AnyTimer> {
+        Box::new(self.clone())
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A component along with the receiving end of the outbox it was given, which the composite empties into its own
+/// after every call into the component
+struct Slot<C: Workload> {
+    workload: C,
+    bodies: Receiver<Body<C>>,
+}
+
+/// The response factory of the composite, handed on to the component handling the request
+type Respond<'a, T> = Box<dyn FnOnce(Value) -> Body<Composite<T>> + 'a>;
+
+/// What the composite `Composite<T>` needs of a [`Slot`], whatever its component
+trait Erased<T: Components>: Send {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn handle_request(&mut self, request: Tagged, src: &NodeId, respond: Respond<'_, T>) -> Result<(), Error>;
+    fn handle_response(&mut self, response: &Value, in_reply_to: MsgId, src: &NodeId);
+    fn handle_timer(&mut self, timer: Box<dyn AnyTimer>);
+
+    /// Forwards everything the component sent to `tx`, as the `index`th component
+    fn forward(&mut self, index: usize, tx: &Sender<Body<Composite<T>>>);
+}
+
+/// A payload flattened into the body, as inside [`MessageBody`](crate::message::MessageBody), so that components
+/// decode their messages exactly as they would on a node of their own
+#[derive(Deserialize)]
+struct Flattened<R> {
+    #[serde(flatten)]
+    payload: R,
+}
+
+fn decode<R: DeserializeOwned>(value: Value) -> Result<R, Error> {
+    serde_json::from_value::<Flattened<R>>(value)
+        .map(|flattened| flattened.payload)
+        .map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))
+}
+
+fn encode(value: &impl Serialize) -> Value {
+    serde_json::to_value(value).expect("serialize body")
+}
+
+/// Whether `C` expects an answer to `request`, which is the case when it claims the `_ok` reply to it
+fn answered<C: Component>(request: &Tagged) -> bool {
+    let reply = format!("{}_ok", request.kind());
+    C::RESPONSE_TYPES.contains(&reply.as_str())
+}
+
+/// Turns a body a component sent into one of the composite. Callbacks are routed back to the component, and
+/// whatever it sends from them is forwarded right away. A request the component expects an answer to is sent as a
+/// call, so that its reply or `error` comes back to the component rather than to whichever claims its `type`.
+fn wrap<C: Component, T: Components>(index: usize, body: Body<C>) -> Body<Composite<T>> {
+    match body {
+        Body::Request { dest, request } => {
+            let request = Tagged(encode(&request));
+            if !answered::<C>(&request) {
+                return Body::Request { dest, request };
+            }
+            Body::Call {
+                dest,
+                request: request.0,
+                retry: None,
+                callback: Box::new(move |composite: &mut Composite<T>, reply, src: &NodeId, in_reply_to| {
+                    composite.with_component::<C>(index, |workload| match reply.and_then(decode) {
+                        Ok(response) => workload.handle_response(&response, in_reply_to, src),
+                        Err(error) => workload.handle_error(&error, in_reply_to, src),
+                    })
+                }),
+            }
+        }
+        Body::Rpc {
+            dest,
+            request,
+            retry,
+            callback,
+        } => Body::Call {
+            dest,
+            request: encode(&request),
+            retry,
+            callback: Box::new(move |composite: &mut Composite<T>, reply, src: &NodeId, _| {
+                composite.with_component::<C>(index, |workload| callback(workload, reply.and_then(decode), src))
+            }),
+        },
+        Body::Call {
+            dest,
+            request,
+            retry,
+            callback,
+        } => Body::Call {
+            dest,
+            request,
+            retry,
+            callback: Box::new(move |composite: &mut Composite<T>, reply, src: &NodeId, msg_id| {
+                composite.with_component::<C>(index, |workload| callback(workload, reply, src, msg_id))
+            }),
+        },
+        Body::Response {
+            dest,
+            in_reply_to,
+            response,
+        } => Body::Response {
+            dest,
+            in_reply_to,
+            response: encode(&response),
+        },
+        Body::Error {
+            dest,
+            in_reply_to,
+            error,
+        } => Body::Error {
+            dest,
+            in_reply_to,
+            error,
+        },
+        Body::Reply {
+            dest,
+            in_reply_to,
+            body,
+        } => Body::Reply {
+            dest,
+            in_reply_to,
+            body,
+        },
+        Body::Timer { timer, schedule } => Body::Timer {
+            timer: Timer {
+                component: index,
+                timer: Box::new(timer),
+            },
+            schedule,
+        },
+    }
+}
+
+impl<C: Component, T: Components> Erased<T> for Slot<C> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn handle_request(&mut self, request: Tagged, src: &NodeId, respond: Respond<'_, T>) -> Result<(), Error> {
+        let request: C::Request = decode(request.0)?;
+        // The reply is built by the composite's factory, which alone knows the request, and taken back apart into
+        // one of the component so that it can be forwarded like anything else the component sends
+        self.workload
+            .handle_request(&request, src, |response| match respond(encode(&response)) {
+                Body::Response { dest, in_reply_to, .. } => Body::Response {
+                    dest,
+                    in_reply_to,
+                    response,
+                },
+                _ => unreachable!("the response factory builds responses"),
+            })
+    }
+
+    fn handle_response(&mut self, response: &Value, in_reply_to: MsgId, src: &NodeId) {
+        match decode::<C::Response>(response.clone()) {
+            Ok(response) => self.workload.handle_response(&response, in_reply_to, src),
+            Err(error) => tracing::warn!(src, in_reply_to, %error, "ignoring response"),
+        }
+    }
+
+    fn handle_timer(&mut self, timer: Box<dyn AnyTimer>) {
+        let timer = timer
+            .into_any()
+            .downcast::<C::Timer>()
+            .expect("timer of this component");
+        self.workload.handle_timer(*timer);
+    }
+
+    fn forward(&mut self, index: usize, tx: &Sender<Body<Composite<T>>>) {
+        while let Ok(body) = self.bodies.try_recv() {
+            tx.send(wrap::<C, T>(index, body)).expect("send failed");
+        }
+    }
+}
+
+/// The class `C` gives `request`, or `None` if `C` does not claim its type
+fn class_of<C: Component>(request: &Tagged) -> Option<Class> {
+    if !C::REQUEST_TYPES.contains(&request.kind()) {
+        return None;
+    }
+    let class = decode::<C::Request>(request.0.clone()).map_or(Class::Protocol, |request| C::class(&request));
+    Some(class)
+}
+
+/// Merges `next` into `queued` as `C` would, or `None` if `C` does not claim the type of `queued`. Requests of
+/// different components are never merged.
+fn coalesce_with<C: Component>(queued: &mut Tagged, next: Tagged) -> Option<Result<(), Tagged>> {
+    if !C::REQUEST_TYPES.contains(&queued.kind()) {
+        return None;
+    }
+    if !C::REQUEST_TYPES.contains(&next.kind()) {
+        return Some(Err(next));
+    }
+    let (Ok(mut merged), Ok(request)) = (
+        decode::<C::Request>(queued.0.clone()),
+        decode::<C::Request>(next.0.clone()),
+    ) else {
+        return Some(Err(next));
+    };
+    Some(match C::coalesce(&mut merged, request) {
+        Ok(()) => {
+            *queued = Tagged(encode(&merged));
+            Ok(())
+        }
+        Err(_) => Err(next),
+    })
+}
+
+/// What a component claims: its name and the `type`s of the requests and responses it handles
+type Claims = (&'static str, &'static [&'static str], &'static [&'static str]);
+
+/// The components of a [`Composite`]: a tuple of up to eight [`Component`]s
+pub trait Components: Sized + Send + 'static {
+    /// Whether any of the components keeps [`Workload::CLOCKS`]
+    #[doc(hidden)]
+    const CLOCKS: bool;
+
+    #[doc(hidden)]
+    #[allow(private_interfaces)]
+    fn start(id: NodeId, all_nodes: HashSet<NodeId>, options: &Options) -> Vec<Box<dyn Erased<Self>>>;
+
+    #[doc(hidden)]
+    fn claims() -> Vec<Claims>;
+
+    /// The [`Workload::class`] of the component claiming the type of `request`
+    #[doc(hidden)]
+    fn class(request: &Tagged) -> Class;
+
+    /// [`Workload::coalesce`] of the component claiming the type of `queued`
+    #[doc(hidden)]
+    fn coalesce(queued: &mut Tagged, next: Tagged) -> Result<(), Tagged>;
+}
+
+macro_rules! components {
+    ($($c:ident),+) => {
+        impl<$($c: Component),+> Components for ($($c,)+) {
+            const CLOCKS: bool = $($c::CLOCKS)||+;
+
+            #[allow(private_interfaces)]
+            fn start(id: NodeId, all_nodes: HashSet<NodeId>, options: &Options) -> Vec<Box<dyn Erased<Self>>> {
+                vec![$({
+                    let (tx, bodies) = channel();
+                    let workload = $c::new(id.clone(), all_nodes.clone(), tx, options);
+                    Box::new(Slot { workload, bodies }) as Box<dyn Erased<Self>>
+                }),+]
+            }
+
+            fn claims() -> Vec<Claims> {
+                vec![$((type_name::<$c>(), $c::REQUEST_TYPES, $c::RESPONSE_TYPES)),+]
+            }
+
+            fn class(request: &Tagged) -> Class {
+                $(if let Some(class) = class_of::<$c>(request) {
+                    return class;
+                })+
+                Class::Protocol
+            }
+
+            fn coalesce(queued: &mut Tagged, next: Tagged) -> Result<(), Tagged> {
+                $(if let Some(merged) = coalesce_with::<$c>(queued, next.clone()) {
+                    return merged;
+                })+
+                Err(next)
             }
-        });
-
-        let (send_b, recv_b) = channel();
-        let tx_b = tx;
-        thread::spawn(move || {
-            for body in recv_b {
-                let wrapped_body = match body {
-                    Body::Request { dest, request } => Body::Request {
-                        dest,
-                        request: Either::B(request),
-                    },
-                    Body::Response {
-                        dest,
-                        in_reply_to,
-                        response,
-                    } => Body::Response {
-                        dest,
-                        in_reply_to,
-                        response: Either::B(response),
-                    },
-                };
-                tx_b.send(wrapped_body).expect("send failed");
+        }
+    };
+}
+
+components!(A);
+components!(A, B);
+components!(A, B, C);
+components!(A, B, C, D);
+components!(A, B, C, D, E);
+components!(A, B, C, D, E, F);
+components!(A, B, C, D, E, F, G);
+components!(A, B, C, D, E, F, G, H);
+
+/// Which component handles each request and response `type`
+#[derive(Debug, Default)]
+struct Routes {
+    requests: BTreeMap<&'static str, usize>,
+    responses: BTreeMap<&'static str, usize>,
+}
+
+impl Routes {
+    fn new(claims: &[Claims]) -> Result<Self, Conflict> {
+        let mut routes = Routes::default();
+        for (index, (_, requests, responses)) in claims.iter().enumerate() {
+            for (table, kinds) in [(&mut routes.requests, requests), (&mut routes.responses, responses)] {
+                for kind in kinds.iter() {
+                    if let Some(first) = table.insert(kind, index) {
+                        return Err(Conflict {
+                            kind,
+                            first: claims[first].0,
+                            second: claims[index].0,
+                        });
+                    }
+                }
             }
-        });
+        }
+        Ok(routes)
+    }
+}
+
+/// Runs several workloads on one node, such as `Composite<(EchoWorkload, KafkaWorkload)>`. Every request and
+/// response goes to the component claiming its `type`, and requests nobody claims are answered with
+/// `not-supported`. Everything runs on the node's thread: what a component sends is passed on once the call into
+/// it returns, so a component should not send from threads of its own.
+pub struct Composite<T: Components> {
+    components: Vec<Box<dyn Erased<T>>>,
+    routes: Routes,
+    tx: Sender<Body<Self>>,
+    types: PhantomData<fn() -> T>,
+}
+
+impl<T: Components> Composite<T> {
+    /// Checks that no two components claim the same message type, which [`Workload::new`] panics on
+    pub fn check() -> Result<(), Conflict> {
+        Routes::new(&T::claims()).map(|_| ())
+    }
+
+    /// Runs `f` on the `index`th component, which must be a `C`, and forwards what it sent
+    fn with_component<C: Component>(&mut self, index: usize, f: impl FnOnce(&mut C)) {
+        let slot = self.components[index]
+            .as_any_mut()
+            .downcast_mut::<Slot<C>>()
+            .expect("component of this type");
+        f(&mut slot.workload);
+        self.forward(index);
+    }
 
-        MultiWorkload {
-            workload_1: P1::new(id.clone(), all_nodes.clone(), send_a),
-            workload_2: P2::new(id, all_nodes, send_b),
+    fn forward(&mut self, index: usize) {
+        self.components[index].forward(index, &self.tx);
+    }
+}
+
+impl<T: Components> Workload for Composite<T> {
+    type Request = Tagged;
+    type Response = Value;
+    type Timer = Timer;
+
+    const CLOCKS: bool = T::CLOCKS;
+
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, options: &Options) -> Self {
+        let routes = Routes::new(&T::claims()).unwrap_or_else(|conflict| panic!("{conflict}"));
+        let mut composite = Composite {
+            components: T::start(id, all_nodes, options),
+            routes,
+            tx,
+            types: PhantomData,
+        };
+        for index in 0..composite.components.len() {
+            composite.forward(index);
         }
+        composite
     }
 
     fn handle_request(
@@ -84,51 +468,38 @@ impl<P1: Workload + 'static, P2: Workload + 'static> Workload for MultiWorkload<
         request: &Self::Request,
         src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    ) {
-        match request {
-            Either::A(req) => self
-                .workload_1
-                .handle_request(req, src, |res| match reponse_factory(Either::A(res)) {
-                    Body::Request {
-                        dest,
-                        request: Either::A(request),
-                    } => Body::Request { dest, request },
-                    Body::Response {
-                        dest,
-                        in_reply_to,
-                        response: Either::A(response),
-                    } => Body::Response {
-                        dest,
-                        in_reply_to,
-                        response,
-                    },
-                    _ => panic!("Workload A sent a reponse type of workload B"),
-                }),
-            Either::B(req) => self
-                .workload_2
-                .handle_request(req, src, |res| match reponse_factory(Either::B(res)) {
-                    Body::Request {
-                        dest,
-                        request: Either::B(request),
-                    } => Body::Request { dest, request },
-                    Body::Response {
-                        dest,
-                        in_reply_to,
-                        response: Either::B(response),
-                    } => Body::Response {
-                        dest,
-                        in_reply_to,
-                        response,
-                    },
-                    _ => panic!("Workload B sent a reponse type of workload A"),
-                }),
-        }
+    ) -> Result<(), Error> {
+        let Some(&index) = self.routes.requests.get(request.kind()) else {
+            let text = format!("no workload handles {:?}", request.kind());
+            return Err(Error::new(ErrorCode::NotSupported, text));
+        };
+        let handled = self.components[index].handle_request(request.clone(), src, Box::new(reponse_factory));
+        self.forward(index);
+        handled
     }
 
     fn handle_response(&mut self, response: &Self::Response, in_reply_to: MsgId, src: &NodeId) {
-        match response {
-            Either::A(res) => self.workload_1.handle_response(res, in_reply_to, src),
-            Either::B(res) => self.workload_2.handle_response(res, in_reply_to, src),
+        let kind = response["type"].as_str().unwrap_or_default();
+        match self.routes.responses.get(kind) {
+            Some(&index) => {
+                self.components[index].handle_response(response, in_reply_to, src);
+                self.forward(index);
+            }
+            None => tracing::warn!(src, ?response, "unexpected response"),
         }
     }
+
+    fn handle_timer(&mut self, timer: Self::Timer) {
+        self.components[timer.component].handle_timer(timer.timer);
+        self.forward(timer.component);
+    }
+
+    /// Gossip of a component stays gossip, so that a full outbox drops it rather than waiting
+    fn class(request: &Self::Request) -> Class {
+        T::class(request)
+    }
+
+    fn coalesce(queued: &mut Self::Request, next: Self::Request) -> Result<(), Self::Request> {
+        T::coalesce(queued, next)
+    }
 }
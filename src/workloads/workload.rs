@@ -1,31 +1,201 @@
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{message::MsgId, node::NodeId};
-use std::{collections::HashSet, sync::mpsc::Sender};
+use crate::{
+    message::{Error, ErrorCode, MsgId},
+    node::NodeId,
+    options::Options,
+    outbox::{Class, Sender},
+    rpc::RetryPolicy,
+    timer::Schedule,
+};
+use std::{collections::HashSet, marker::PhantomData};
+
+/// Continuation run on the node's thread with the typed response to an [`Body::Rpc`], or the error it was answered
+/// with. The `NodeId` is the node the request was sent to.
+pub type ResponseCallback<W> = Box<dyn FnOnce(&mut W, Result<<W as Workload>::Response, Error>, &NodeId) + Send>;
+
+/// Continuation run with the undecoded body of the reply to a [`Body::Call`], or the error it was answered with.
+/// The `MsgId` is the one the request went out with.
+pub type ReplyCallback<W> = Box<dyn FnOnce(&mut W, Result<serde_json::Value, Error>, &NodeId, MsgId) + Send>;
 
 pub enum Body<W: Workload + ?Sized> {
     Request {
         dest: NodeId,
         request: W::Request,
     },
+    /// A request whose response is handed to `callback` instead of [`Workload::handle_response`]. With a `retry`
+    /// policy the request is resent until it is answered, and the callback fails with
+    /// [`ErrorCode::Timeout`](crate::message::ErrorCode::Timeout) once the policy gives up.
+    Rpc {
+        dest: NodeId,
+        request: W::Request,
+        retry: Option<RetryPolicy>,
+        callback: ResponseCallback<W>,
+    },
+    /// Like [`Body::Rpc`], but for a request outside of the workload's own message types, such as one to a
+    /// Maelstrom service. The reply is handed to `callback` as plain JSON.
+    Call {
+        dest: NodeId,
+        request: serde_json::Value,
+        retry: Option<RetryPolicy>,
+        callback: ReplyCallback<W>,
+    },
     Response {
         dest: NodeId,
         in_reply_to: MsgId,
         response: W::Response,
     },
+    Error {
+        dest: NodeId,
+        in_reply_to: MsgId,
+        error: Error,
+    },
+    /// A reply outside of the workload's own message types, such as the answer to a built-in request
+    Reply {
+        dest: NodeId,
+        in_reply_to: MsgId,
+        body: serde_json::Value,
+    },
+    /// Registers a timer that is handed back to [`Workload::handle_timer`] according to `schedule`
+    Timer {
+        timer: W::Timer,
+        schedule: Schedule,
+    },
+}
+
+impl<W: Workload + ?Sized> Body<W> {
+    /// The node the body is sent to, if it is a message
+    pub(crate) fn dest(&self) -> Option<&NodeId> {
+        match self {
+            Body::Request { dest, .. }
+            | Body::Rpc { dest, .. }
+            | Body::Call { dest, .. }
+            | Body::Response { dest, .. }
+            | Body::Error { dest, .. }
+            | Body::Reply { dest, .. } => Some(dest),
+            Body::Timer { .. } => None,
+        }
+    }
+
+    /// Builds an [`Body::Rpc`] whose `callback` runs once the response from `dest` arrives
+    pub fn rpc(
+        dest: NodeId,
+        request: W::Request,
+        callback: impl FnOnce(&mut W, Result<W::Response, Error>, &NodeId) + Send + 'static,
+    ) -> Self {
+        Body::Rpc {
+            dest,
+            request,
+            retry: None,
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Builds an [`Body::Rpc`] that is resent according to `retry` until `dest` answers
+    pub fn rpc_with_retry(
+        dest: NodeId,
+        request: W::Request,
+        retry: RetryPolicy,
+        callback: impl FnOnce(&mut W, Result<W::Response, Error>, &NodeId) + Send + 'static,
+    ) -> Self {
+        Body::Rpc {
+            dest,
+            request,
+            retry: Some(retry),
+            callback: Box::new(callback),
+        }
+    }
+
+    /// Builds a [`Body::Call`] that sends `request` to `dest` and decodes the reply as `R`
+    pub fn call<R: DeserializeOwned>(
+        dest: NodeId,
+        request: &impl Serialize,
+        retry: Option<RetryPolicy>,
+        callback: impl FnOnce(&mut W, Result<R, Error>, &NodeId) + Send + 'static,
+    ) -> Self {
+        let request = serde_json::to_value(request).expect("serialize request");
+        Body::Call {
+            dest,
+            request,
+            retry,
+            callback: decode_reply(callback),
+        }
+    }
+}
+
+/// Adapts a callback expecting a reply of type `R` to one that takes the reply as plain JSON
+pub(crate) fn decode_reply<W: ?Sized, R: DeserializeOwned>(
+    callback: impl FnOnce(&mut W, Result<R, Error>, &NodeId) + Send + 'static,
+) -> ReplyCallback<W> {
+    Box::new(move |workload, reply, src, _| {
+        let reply = reply.and_then(|body| {
+            serde_json::from_value(body).map_err(|err| Error::new(ErrorCode::MalformedRequest, err.to_string()))
+        });
+        callback(workload, reply, src)
+    })
+}
+
+/// The answer to one request, which only takes `R`, the response the request is paired with. Built by the
+/// `Request::incoming` that [`messages`](crate::messages) writes.
+pub struct Reply<'a, W: Workload + ?Sized, R> {
+    factory: Box<dyn FnOnce(W::Response) -> Body<W> + 'a>,
+    response: PhantomData<fn(R)>,
+}
+
+impl<'a, W: Workload + ?Sized, R: Into<W::Response>> Reply<'a, W, R> {
+    pub fn new(factory: impl FnOnce(W::Response) -> Body<W> + 'a) -> Self {
+        Reply {
+            factory: Box::new(factory),
+            response: PhantomData,
+        }
+    }
+
+    /// The body answering the request with `response`, to put in the outbox
+    pub fn with(self, response: R) -> Body<W> {
+        (self.factory)(response.into())
+    }
 }
 
 pub trait Workload {
     type Request: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send;
     type Response: DeserializeOwned + Serialize + Clone + std::fmt::Debug + Send;
+    type Timer: Clone + std::fmt::Debug + Send;
 
-    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>) -> Self;
+    /// Whether the node keeps [`clock`](crate::clock)s for the workload: every message to another node is stamped
+    /// with them and every stamped message received advances them. Handlers read them with
+    /// [`clock::now`](crate::clock::now) and [`clock::received`](crate::clock::received).
+    const CLOCKS: bool = false;
 
+    /// Starts the workload of node `id`, which sends through `tx` and reads its flags from `options`
+    fn new(id: NodeId, all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, options: &Options) -> Self;
+
+    /// Handles a request. Returning an error answers it with an `error` reply instead.
     fn handle_request(
         &mut self,
         request: &Self::Request,
         src: &NodeId,
         reponse_factory: impl FnOnce(Self::Response) -> Body<Self>,
-    );
+    ) -> Result<(), Error>;
+
+    /// Handles a response that has no callback registered through [`Body::Rpc`] or [`Body::Call`]
     fn handle_response(&mut self, response: &Self::Response, in_reply_to: MsgId, src: &NodeId);
+
+    /// Handles an `error` reply that has no callback registered through [`Body::Rpc`] or [`Body::Call`]
+    fn handle_error(&mut self, error: &Error, in_reply_to: MsgId, src: &NodeId) {
+        tracing::warn!(src, in_reply_to, %error, "request failed");
+    }
+
+    /// Handles a timer registered through [`Body::Timer`], on the same thread as every other handler
+    fn handle_timer(&mut self, _timer: Self::Timer) {}
+
+    /// Merges `next` into `queued`, both [`Body::Request`]s to the same node held back by the outbox, or hands
+    /// `next` back if they can't be merged. Only called with [`BATCH_WINDOW`](crate::batch::BATCH_WINDOW) set.
+    fn coalesce(_queued: &mut Self::Request, next: Self::Request) -> Result<(), Self::Request> {
+        Err(next)
+    }
+
+    /// What `request` is for when sent to another node. [`Class::Gossip`] is dropped when the outbox is full.
+    fn class(_request: &Self::Request) -> Class {
+        Class::Protocol
+    }
 }
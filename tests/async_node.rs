@@ -0,0 +1,315 @@
+#![cfg(feature = "async")]
+
+use dist_sys_challenge::{
+    async_node::{AsyncNode, AsyncWorkload, Context, SyncAdapter},
+    message::{Error, ErrorCode, MsgId},
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    rpc::RetryPolicy,
+    timer::Schedule,
+    workloads::workload::{Body, Workload},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines};
+use tokio::time;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    /// Pings `dest`, once and waiting however long it takes when `attempts` is 0
+    Relay {
+        dest: NodeId,
+        attempts: u32,
+    },
+    Ping,
+    /// Pings `dest` without waiting for the reply
+    Notify {
+        dest: NodeId,
+    },
+    Heard,
+    /// Starts a timer with the given period in milliseconds
+    Tick {
+        period: u64,
+    },
+    /// Replies after the given number of milliseconds
+    Sleep {
+        millis: u64,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+enum Response {
+    RelayOk,
+    PingOk,
+    NotifyOk,
+    HeardOk { replies: Vec<Value>, ticks: u64 },
+    TickOk,
+    SleepOk,
+}
+
+struct Relay {
+    ctx: Context<Self>,
+}
+
+impl AsyncWorkload for Relay {
+    type Request = Request;
+    type Response = Response;
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, ctx: Context<Self>) -> Self {
+        Relay { ctx }
+    }
+
+    async fn handle_request(&self, request: Request, _src: NodeId, _msg_id: MsgId) -> Result<Option<Response>, Error> {
+        match request {
+            Request::Relay { dest, attempts: 0 } => self.ctx.rpc(dest, Request::Ping).await?,
+            Request::Relay { dest, attempts } => {
+                let retry = RetryPolicy::new(Duration::from_millis(50), attempts)
+                    .with_backoff(Duration::from_millis(10), Duration::from_millis(10));
+                self.ctx.rpc_with_retry(dest, Request::Ping, retry).await?
+            }
+            Request::Ping => return Ok(Some(Response::PingOk)),
+            Request::Sleep { millis } => {
+                time::sleep(Duration::from_millis(millis)).await;
+                return Ok(Some(Response::SleepOk));
+            }
+            _ => return Err(Error::new(ErrorCode::NotSupported, "relays only")),
+        };
+        Ok(Some(Response::RelayOk))
+    }
+}
+
+/// A sync workload that notes the replies to the pings it sends, which reach it through [`SyncAdapter`]
+struct Listener {
+    tx: Sender<Body<Self>>,
+    replies: Vec<Value>,
+    ticks: u64,
+}
+
+impl Workload for Listener {
+    type Request = Request;
+    type Response = Response;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Listener {
+            tx,
+            replies: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Response) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request {
+            Request::Notify { dest } => {
+                let ping = Body::Request {
+                    dest: dest.clone(),
+                    request: Request::Ping,
+                };
+                self.tx.send(ping).expect("send failed");
+                Response::NotifyOk
+            }
+            Request::Heard => Response::HeardOk {
+                replies: self.replies.clone(),
+                ticks: self.ticks,
+            },
+            Request::Tick { period } => {
+                let schedule = Schedule::Every(Duration::from_millis(*period));
+                self.tx.send(Body::Timer { timer: (), schedule }).expect("send failed");
+                Response::TickOk
+            }
+            _ => return Err(Error::new(ErrorCode::NotSupported, "listens only")),
+        };
+        self.tx.send(reponse_factory(response)).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, response: &Response, _in_reply_to: MsgId, _src: &NodeId) {
+        self.replies.push(json!(response));
+    }
+
+    fn handle_error(&mut self, error: &Error, _in_reply_to: MsgId, _src: &NodeId) {
+        self.replies.push(json!({"code": error.code}));
+    }
+
+    fn handle_timer(&mut self, _timer: ()) {
+        self.ticks += 1;
+    }
+}
+
+/// Both ends of a node n1 in a cluster with n2, as its peers see them
+struct Wire {
+    input: DuplexStream,
+    output: Lines<BufReader<DuplexStream>>,
+}
+
+impl Wire {
+    async fn start<W: AsyncWorkload>() -> Self {
+        let (input, node_input) = tokio::io::duplex(1 << 16);
+        let (node_output, output) = tokio::io::duplex(1 << 16);
+        tokio::spawn(async {
+            if let Some(node) = AsyncNode::<W>::init_with(node_input, node_output).await {
+                node.run().await;
+            }
+        });
+        let mut wire = Wire {
+            input,
+            output: BufReader::new(output).lines(),
+        };
+        let init = json!({"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]});
+        wire.send("c1", init).await;
+        assert_eq!(wire.recv().await["body"]["type"], "init_ok");
+        wire
+    }
+
+    async fn send(&mut self, src: &str, body: Value) {
+        let line = format!("{}\n", json!({"src": src, "dest": "n1", "body": body}));
+        self.input.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    /// Closes n1's input, as Maelstrom does at the end of a test
+    async fn close(&mut self) {
+        self.input.shutdown().await.unwrap();
+    }
+
+    async fn recv(&mut self) -> Value {
+        let line = time::timeout(Duration::from_secs(5), self.output.next_line())
+            .await
+            .expect("no message within 5s")
+            .unwrap()
+            .expect("output closed");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// Receives a ping n1 sent to n2, and returns its msg_id
+    async fn recv_ping(&mut self) -> u64 {
+        let ping = self.recv().await;
+        assert_eq!(ping["dest"], "n2");
+        assert_eq!(ping["body"]["type"], "ping");
+        ping["body"]["msg_id"].as_u64().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn rpc_waits_for_the_response() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "relay", "msg_id": 2, "dest": "n2", "attempts": 0}))
+        .await;
+
+    let ping = wire.recv_ping().await;
+    // No timeout, so nothing is resent while n2 takes its time
+    time::sleep(Duration::from_millis(200)).await;
+    wire.send("n2", json!({"type": "ping_ok", "in_reply_to": ping})).await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["dest"], "c1");
+    assert_eq!(reply["body"], json!({"type": "relay_ok", "in_reply_to": 2}));
+}
+
+#[tokio::test]
+async fn rpc_with_retry_resends_until_answered() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "relay", "msg_id": 2, "dest": "n2", "attempts": 3}))
+        .await;
+
+    let first = wire.recv_ping().await;
+    let second = wire.recv_ping().await;
+    assert_eq!(first, second);
+    wire.send("n2", json!({"type": "ping_ok", "in_reply_to": second})).await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["body"], json!({"type": "relay_ok", "in_reply_to": 2}));
+}
+
+#[tokio::test]
+async fn rpc_with_retry_times_out_after_the_last_attempt() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "relay", "msg_id": 2, "dest": "n2", "attempts": 2}))
+        .await;
+
+    wire.recv_ping().await;
+    wire.recv_ping().await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["dest"], "c1");
+    assert_eq!(reply["body"]["type"], "error");
+    assert_eq!(reply["body"]["code"], 0);
+    assert_eq!(reply["body"]["in_reply_to"], 2);
+}
+
+#[tokio::test]
+async fn metrics_are_not_supported() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "metrics", "msg_id": 2})).await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["body"]["type"], "error");
+    assert_eq!(reply["body"]["code"], 10);
+}
+
+#[tokio::test]
+async fn sync_workloads_handle_replies_nobody_waits_for() {
+    let mut wire = Wire::start::<SyncAdapter<Listener>>().await;
+    for (msg_id, mut reply) in [
+        (2, json!({"type": "ping_ok"})),
+        (3, json!({"type": "error", "code": 11})),
+    ] {
+        wire.send("c1", json!({"type": "notify", "msg_id": msg_id, "dest": "n2"}))
+            .await;
+        reply["in_reply_to"] = wire.recv_ping().await.into();
+        assert_eq!(wire.recv().await["body"]["type"], "notify_ok");
+        wire.send("n2", reply).await;
+    }
+
+    wire.send("c1", json!({"type": "heard", "msg_id": 4})).await;
+    let heard = wire.recv().await;
+    assert_eq!(heard["body"]["replies"], json!([{"type": "ping_ok"}, {"code": 11}]));
+}
+
+#[tokio::test]
+async fn sync_timers_with_a_zero_period_still_tick() {
+    let mut wire = Wire::start::<SyncAdapter<Listener>>().await;
+    wire.send("c1", json!({"type": "tick", "msg_id": 2, "period": 0})).await;
+    assert_eq!(wire.recv().await["body"]["type"], "tick_ok");
+
+    time::sleep(Duration::from_millis(100)).await;
+    wire.send("c1", json!({"type": "heard", "msg_id": 3})).await;
+    let ticks = wire.recv().await["body"]["ticks"].as_u64().unwrap();
+    assert!(ticks > 10, "ticked {ticks} times");
+}
+
+#[tokio::test]
+async fn handlers_still_reply_after_the_input_closes() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "sleep", "msg_id": 2, "millis": 100}))
+        .await;
+    wire.close().await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["body"], json!({"type": "sleep_ok", "in_reply_to": 2}));
+}
+
+#[tokio::test]
+async fn rpcs_fail_once_the_input_closes() {
+    let mut wire = Wire::start::<Relay>().await;
+    wire.send("c1", json!({"type": "relay", "msg_id": 2, "dest": "n2", "attempts": 0}))
+        .await;
+    wire.recv_ping().await;
+    // No reply can arrive anymore, so the relay fails instead of waiting forever
+    wire.close().await;
+
+    let reply = wire.recv().await;
+    assert_eq!(reply["body"]["type"], "error");
+    assert_eq!(reply["body"]["code"], 13);
+    assert_eq!(reply["body"]["in_reply_to"], 2);
+}
@@ -0,0 +1,186 @@
+use dist_sys_challenge::{
+    batch::BATCH_WINDOW,
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    sim::Simulation,
+    workloads::{
+        broadcast::{BroadcastWorkload, Request},
+        echo::EchoWorkload,
+        multi::{Composite, Tagged},
+        workload::{Body, Workload},
+    },
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+mod common;
+
+/// Broadcasts a burst of values to n1 and returns how many gossip messages it took to spread them
+fn gossip_sent(window: &str) -> u64 {
+    let options = Options::default().with(&BATCH_WINDOW, window);
+    let mut sim = Simulation::<BroadcastWorkload>::new_with_options(5, 11, |_| options.clone());
+    let nodes: Vec<_> = sim.nodes().cloned().collect();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    for value in 0..20 {
+        sim.send("c1", "n1", json!({"type": "broadcast", "message": value}));
+    }
+    sim.run_for(Duration::from_secs(3));
+
+    for node in &nodes {
+        let reply = sim.rpc("c1", node, json!({"type": "read"}));
+        assert_eq!(
+            reply["messages"],
+            json!((0..20).collect::<Vec<_>>()),
+            "{node} with window {window}"
+        );
+    }
+    nodes
+        .iter()
+        .map(|node| {
+            sim.metrics(node)
+                .sent
+                .by_type
+                .get("gossip")
+                .copied()
+                .unwrap_or_default()
+        })
+        .sum()
+}
+
+#[test]
+fn batching_merges_gossip_to_the_same_node() {
+    let unbatched = gossip_sent("0ms");
+    let batched = gossip_sent("100ms");
+    assert!(
+        batched < unbatched,
+        "{batched} gossip messages batched, {unbatched} without"
+    );
+
+    let mut queued = Request::Gossip {
+        values: BTreeSet::from([1, 2]),
+    };
+    let next = Request::Gossip {
+        values: BTreeSet::from([2, 3]),
+    };
+    assert!(BroadcastWorkload::coalesce(&mut queued, next).is_ok());
+    assert!(matches!(queued, Request::Gossip { values } if values == BTreeSet::from([1, 2, 3])));
+    assert!(BroadcastWorkload::coalesce(&mut Request::Read, Request::Read).is_err());
+}
+
+#[test]
+fn a_composite_merges_requests_of_the_same_component() {
+    type Combined = Composite<(EchoWorkload, BroadcastWorkload)>;
+    let mut queued = Tagged(json!({"type": "gossip", "messages": [1, 2]}));
+    let next = Tagged(json!({"type": "gossip", "messages": [2, 3]}));
+    assert!(Combined::coalesce(&mut queued, next).is_ok());
+    assert_eq!(queued.0, json!({"type": "gossip", "messages": [1, 2, 3]}));
+
+    let echo = Tagged(json!({"type": "echo", "echo": "hi"}));
+    let unmerged = Combined::coalesce(&mut queued, echo.clone()).unwrap_err();
+    assert_eq!(unmerged.0, echo.0);
+    assert!(Combined::coalesce(&mut echo.clone(), echo).is_err());
+}
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Note {
+    #[ok]
+    Send {
+        notes: Vec<String>,
+    },
+    Add {
+        notes: Vec<String>,
+    },
+    Mark,
+    #[ok(received: Vec<String>)]
+    Read,
+}
+
+/// Sends `add`s and `mark`s to n2, which keeps the order they arrived in. `add`s merge.
+struct Notes {
+    tx: Sender<Body<Self>>,
+    received: Vec<String>,
+}
+
+impl Workload for Notes {
+    type Request = Note;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Notes {
+            tx,
+            received: Vec::new(),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Note,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Send { notes, reply } => {
+                for note in notes {
+                    let request = match note.as_str() {
+                        "mark" => Note::Mark,
+                        note => Note::Add {
+                            notes: vec![note.to_string()],
+                        },
+                    };
+                    let dest = "n2".to_string();
+                    self.tx.send(Body::Request { dest, request }).expect("send failed");
+                }
+                reply.with(SendOk {})
+            }
+            Handle::Add { notes } => {
+                self.received.push(notes.join("+"));
+                return Ok(());
+            }
+            Handle::Mark {} => {
+                self.received.push("mark".to_string());
+                return Ok(());
+            }
+            Handle::Read { reply } => reply.with(ReadOk {
+                received: self.received.clone(),
+            }),
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+
+    fn coalesce(queued: &mut Note, next: Note) -> Result<(), Note> {
+        match (queued, next) {
+            (Note::Add { notes }, Note::Add { notes: more }) => {
+                notes.extend(more);
+                Ok(())
+            }
+            (_, next) => Err(next),
+        }
+    }
+}
+
+#[test]
+fn batching_keeps_the_order_of_requests_to_a_node() {
+    // A steady network, so that it doesn't reorder what the outbox wrote in order
+    let options = Options::default().with(&BATCH_WINDOW, "100ms");
+    let mut sim = common::steady_with_options::<Notes>(2, 1, options);
+
+    sim.rpc(
+        "c1",
+        "n1",
+        json!({"type": "send", "notes": ["a", "b", "mark", "c", "d"]}),
+    );
+    sim.run_for(Duration::from_secs(1));
+    let reply = sim.rpc("c1", "n2", json!({"type": "read"}));
+    assert_eq!(reply["received"], json!(["a+b", "mark", "c+d"]));
+}
@@ -0,0 +1,56 @@
+use dist_sys_challenge::{
+    cluster::{Client, Cluster},
+    workloads::broadcast::BroadcastWorkload,
+};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn read(client: &mut Client, node: &str) -> BTreeSet<i64> {
+    let reply = client.rpc(node, json!({"type": "read"}));
+    assert_eq!(reply["type"], "read_ok");
+    reply["messages"]
+        .as_array()
+        .expect("messages")
+        .iter()
+        .map(|value| value.as_i64().expect("integer message"))
+        .collect()
+}
+
+#[test]
+fn values_reach_every_node() {
+    let cluster = Cluster::start::<BroadcastWorkload>(5);
+    let mut c1 = cluster.client("c1");
+
+    let nodes = cluster.nodes().to_vec();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        let reply = c1.rpc(node, json!({"type": "topology", "topology": topology}));
+        assert_eq!(reply["type"], "topology_ok");
+    }
+
+    let expected: BTreeSet<i64> = (0..10).collect();
+    for (value, node) in expected.iter().zip(nodes.iter().cycle()) {
+        let reply = c1.rpc(node, json!({"type": "broadcast", "message": value}));
+        assert_eq!(reply["type"], "broadcast_ok");
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    for node in &nodes {
+        while read(&mut c1, node) != expected {
+            assert!(Instant::now() < deadline, "{node} did not see every value");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[test]
+fn topology_must_include_the_node() {
+    let cluster = Cluster::start::<BroadcastWorkload>(2);
+    let mut c1 = cluster.client("c1");
+
+    let reply = c1.rpc("n1", json!({"type": "topology", "topology": {"n2": []}}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 12);
+}
@@ -0,0 +1,124 @@
+use dist_sys_challenge::{
+    clock::{self, Clocks, Lamport, VersionVector},
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    sim::Simulation,
+    workloads::workload::{Body, Workload},
+};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Start {
+        dest: NodeId,
+    },
+    Ping,
+    #[ok(now: Option<Clocks>, received: Option<Clocks>)]
+    Read,
+}
+
+/// Pings another node on request, and remembers the stamp of the last ping it got
+struct Relay {
+    tx: Sender<Body<Self>>,
+    received: Option<Clocks>,
+}
+
+impl Workload for Relay {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    const CLOCKS: bool = true;
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Relay { tx, received: None }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Start { dest, reply } => {
+                let ping = Body::Request {
+                    dest: dest.clone(),
+                    request: Request::Ping,
+                };
+                self.tx.send(ping).expect("send failed");
+                reply.with(StartOk {})
+            }
+            Handle::Ping {} => {
+                self.received = clock::received();
+                return Ok(());
+            }
+            Handle::Read { reply } => reply.with(ReadOk {
+                now: clock::now(),
+                received: self.received.clone(),
+            }),
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+fn vector(versions: Value) -> VersionVector {
+    serde_json::from_value(versions).unwrap()
+}
+
+#[test]
+fn messages_between_nodes_carry_the_clocks() {
+    let mut sim = Simulation::<Relay>::new(2, 1);
+    sim.rpc("c1", "n1", json!({"type": "start", "dest": "n2"}));
+    sim.rpc("c1", "n2", json!({"type": "start", "dest": "n1"}));
+
+    // n1 stamped its ping with its first send, which n2 observed before sending its own
+    let n2 = sim.rpc("c1", "n2", json!({"type": "read"}));
+    assert_eq!(n2["received"], json!({"lamport": 1, "vector": {"n1": 1}}));
+    assert_eq!(n2["now"], json!({"lamport": 3, "vector": {"n1": 1, "n2": 1}}));
+    let n1 = sim.rpc("c1", "n1", json!({"type": "read"}));
+    assert_eq!(n1["received"], json!({"lamport": 3, "vector": {"n1": 1, "n2": 1}}));
+    assert_eq!(n1["now"], json!({"lamport": 4, "vector": {"n1": 1, "n2": 1}}));
+
+    // Only messages between nodes are stamped
+    let lines: Vec<Value> = sim
+        .history()
+        .iter()
+        .map(|(_, line)| serde_json::from_str(line).unwrap())
+        .collect();
+    for line in &lines {
+        let between_nodes =
+            line["src"].as_str().unwrap().starts_with('n') && line["dest"].as_str().unwrap().starts_with('n');
+        assert_eq!(line.get("clocks").is_some(), between_nodes, "{line}");
+    }
+}
+
+#[test]
+fn clocks_order_events() {
+    let mut lamport = Lamport::default();
+    assert_eq!(lamport.tick(), Lamport(1));
+    assert_eq!(lamport.observe(Lamport(5)), Lamport(6));
+    assert_eq!(lamport.observe(Lamport(2)), Lamport(7));
+
+    let a = vector(json!({"n1": 2, "n2": 1}));
+    let b = vector(json!({"n1": 1, "n2": 1}));
+    let c = vector(json!({"n1": 1, "n2": 2}));
+    assert_eq!(a.partial_cmp(&b), Some(Ordering::Greater));
+    assert_eq!(b.partial_cmp(&c), Some(Ordering::Less));
+    assert!(a.concurrent_with(&c));
+    assert_eq!(b.partial_cmp(&b.clone()), Some(Ordering::Equal));
+
+    let mut merged = a.clone();
+    merged.merge(&c);
+    assert_eq!(merged, vector(json!({"n1": 2, "n2": 2})));
+    assert!(merged > a && merged > c);
+}
@@ -0,0 +1,36 @@
+//! Setup shared by the integration tests. Every test file that needs it declares `mod common;` and uses what it
+//! needs, so not every helper is used by every file.
+#![allow(dead_code)]
+
+use dist_sys_challenge::{message::Error, options::Options, sim::Simulation, workloads::workload::Workload};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// A simulation of `n` nodes in which every message takes exactly 1ms, so that the network neither reorders
+/// messages nor blurs the timing of what the nodes send
+pub fn steady<W: Workload + 'static>(n: usize, seed: u64) -> Simulation<W> {
+    steady_with_options(n, seed, Options::default())
+}
+
+/// Like [`steady`], starting every node with `options`
+pub fn steady_with_options<W: Workload + 'static>(n: usize, seed: u64, options: Options) -> Simulation<W> {
+    let latency = Duration::from_millis(1);
+    Simulation::new_with_options(n, seed, |_| options.clone()).with_latency(latency, latency)
+}
+
+/// What a callback was handed, as JSON: the value itself, or the code of the error
+pub fn outcome<T: Serialize>(result: Result<T, Error>) -> Value {
+    match result {
+        Ok(value) => json!(value),
+        Err(error) => json!({"code": error.code}),
+    }
+}
+
+/// Every message delivered so far, with the virtual time it arrived at
+pub fn delivered<W: Workload + 'static>(sim: &Simulation<W>) -> Vec<(Duration, Value)> {
+    sim.history()
+        .iter()
+        .map(|(at, line)| (*at, serde_json::from_str(line).unwrap()))
+        .collect()
+}
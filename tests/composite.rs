@@ -0,0 +1,189 @@
+use dist_sys_challenge::{
+    cluster::Cluster,
+    message::{Error, ErrorCode, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    workloads::{
+        broadcast::BroadcastWorkload,
+        echo::EchoWorkload,
+        g_counter::GCounterWorkload,
+        generate::GenerateWorkload,
+        kafka::KafkaWorkload,
+        multi::{Component, Composite, Conflict},
+        workload::{Body, Workload},
+    },
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Knock { dest: NodeId, welcome: bool },
+    #[ok]
+    Visit { welcome: bool },
+    #[ok(answers: Vec<Value>)]
+    Answers,
+}
+
+/// Visits `dest` on a knock with a plain request, which is turned away unless welcome, and notes the answers along
+/// with the request they answer
+struct Visitor {
+    tx: Sender<Body<Self>>,
+    answers: Vec<Value>,
+}
+
+impl Workload for Visitor {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    const CLOCKS: bool = true;
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Visitor {
+            tx,
+            answers: Vec::new(),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Knock { dest, welcome, reply } => {
+                let visit = Body::Request {
+                    dest: dest.clone(),
+                    request: Request::Visit { welcome: *welcome },
+                };
+                self.tx.send(visit).expect("send failed");
+                reply.with(KnockOk {})
+            }
+            Handle::Visit { welcome: false, .. } => {
+                return Err(Error::new(ErrorCode::PreconditionFailed, "not welcome"));
+            }
+            Handle::Visit { reply, .. } => reply.with(VisitOk {}),
+            Handle::Answers { reply } => reply.with(AnswersOk {
+                answers: self.answers.clone(),
+            }),
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, response: &Reply, in_reply_to: MsgId, _src: &NodeId) {
+        self.answers
+            .push(json!({"reply": response, "in_reply_to": in_reply_to}));
+    }
+
+    fn handle_error(&mut self, error: &Error, in_reply_to: MsgId, _src: &NodeId) {
+        self.answers
+            .push(json!({"code": error.code, "in_reply_to": in_reply_to}));
+    }
+}
+
+impl Component for Visitor {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+    const RESPONSE_TYPES: &'static [&'static str] = Reply::TYPES;
+}
+
+#[test]
+fn requests_go_to_the_component_claiming_their_type() {
+    let cluster = Cluster::start::<Composite<(EchoWorkload, GenerateWorkload, KafkaWorkload)>>(1);
+    let mut c1 = cluster.client("c1");
+
+    let reply = c1.rpc("n1", json!({"type": "echo", "echo": "hello"}));
+    assert_eq!(reply["type"], "echo_ok");
+    assert_eq!(reply["echo"], "hello");
+
+    let reply = c1.rpc("n1", json!({"type": "generate"}));
+    assert_eq!(reply["type"], "generate_ok");
+
+    let reply = c1.rpc("n1", json!({"type": "send", "key": "k", "msg": 7}));
+    assert_eq!(reply["type"], "send_ok");
+    let reply = c1.rpc("n1", json!({"type": "poll", "offsets": {"k": 0}}));
+    assert_eq!(reply["type"], "poll_ok");
+    assert_eq!(reply["msgs"]["k"][0][1], 7);
+
+    let reply = c1.rpc("n1", json!({"type": "dance"}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 10);
+}
+
+#[test]
+fn components_keep_their_timers_and_peers() {
+    let cluster = Cluster::start::<Composite<(EchoWorkload, BroadcastWorkload)>>(3);
+    let mut c1 = cluster.client("c1");
+    let nodes = cluster.nodes().to_vec();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        c1.rpc(node, json!({"type": "topology", "topology": topology}));
+    }
+    c1.rpc("n1", json!({"type": "broadcast", "message": 42}));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    for node in &nodes {
+        while c1.rpc(node, json!({"type": "read"}))["messages"] != json!([42]) {
+            assert!(Instant::now() < deadline, "{node} did not see the value");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[test]
+fn two_components_claiming_a_type_conflict() {
+    let conflict = Composite::<(EchoWorkload, BroadcastWorkload, GCounterWorkload)>::check().unwrap_err();
+    assert_eq!(
+        conflict,
+        Conflict {
+            kind: "read",
+            first: std::any::type_name::<BroadcastWorkload>(),
+            second: std::any::type_name::<GCounterWorkload>(),
+        }
+    );
+    assert!(Composite::<(EchoWorkload, BroadcastWorkload, KafkaWorkload)>::check().is_ok());
+}
+
+#[test]
+fn answers_to_a_component_go_back_to_it() {
+    let mut sim = common::steady::<Composite<(EchoWorkload, Visitor)>>(2, 7);
+    sim.rpc("c1", "n1", json!({"type": "knock", "dest": "n2", "welcome": true}));
+    sim.rpc("c1", "n1", json!({"type": "knock", "dest": "n2", "welcome": false}));
+    sim.run_for(Duration::from_millis(50));
+
+    let visits: Vec<Value> = common::delivered(&sim)
+        .into_iter()
+        .filter(|(_, msg)| msg["body"]["type"] == "visit")
+        .map(|(_, msg)| msg["body"]["msg_id"].clone())
+        .collect();
+    assert_eq!(visits.len(), 2);
+    let reply = sim.rpc("c1", "n1", json!({"type": "answers"}));
+    assert_eq!(
+        reply["answers"],
+        json!([
+            {"reply": {"type": "visit_ok"}, "in_reply_to": visits[0]},
+            {"code": 22, "in_reply_to": visits[1]},
+        ])
+    );
+}
+
+#[test]
+fn a_composite_keeps_clocks_when_any_component_does() {
+    let mut sim = common::steady::<Composite<(EchoWorkload, Visitor)>>(2, 7);
+    sim.rpc("c1", "n1", json!({"type": "knock", "dest": "n2", "welcome": true}));
+    sim.run_for(Duration::from_millis(50));
+    for (_, msg) in common::delivered(&sim) {
+        let between_nodes =
+            msg["src"].as_str().unwrap().starts_with('n') && msg["dest"].as_str().unwrap().starts_with('n');
+        assert_eq!(msg.get("clocks").is_some(), between_nodes, "{msg}");
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn read_lines(path: &Path) -> Vec<Value> {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| panic!("reading {} failed: {err}", path.display()));
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|err| panic!("{line}: {err}")))
+        .collect()
+}
+
+/// Compares an actual message to an expected one, where `"$string"` and `"$uuid"` match any string and any UUID.
+/// Returns the path to the first difference.
+fn mismatch(expected: &Value, actual: &Value, path: &str) -> Option<String> {
+    match (expected, actual) {
+        (Value::String(placeholder), Value::String(_)) if placeholder == "$string" => None,
+        (Value::String(placeholder), Value::String(actual)) if placeholder == "$uuid" => uuid::Uuid::parse_str(actual)
+            .is_err()
+            .then(|| format!("{path}: {actual:?} is not a uuid")),
+        (Value::Object(expected), Value::Object(actual)) => {
+            let keys: HashSet<&String> = expected.keys().chain(actual.keys()).collect();
+            let mut keys: Vec<&String> = keys.into_iter().collect();
+            keys.sort();
+            keys.into_iter()
+                .find_map(|key| match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => mismatch(expected, actual, &format!("{path}.{key}")),
+                    (Some(_), None) => Some(format!("{path}.{key} is missing")),
+                    (None, Some(_)) => Some(format!("{path}.{key} is unexpected")),
+                    (None, None) => None,
+                })
+        }
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => expected
+            .iter()
+            .zip(actual)
+            .enumerate()
+            .find_map(|(i, (expected, actual))| mismatch(expected, actual, &format!("{path}[{i}]"))),
+        _ => (expected != actual).then(|| format!("{path}: expected {expected}, got {actual}")),
+    }
+}
+
+/// The clients in some input: every sender that the init messages don't name as a node
+fn clients(input: &[Value]) -> HashSet<String> {
+    let nodes: HashSet<&str> = input
+        .iter()
+        .filter(|msg| msg["body"]["type"] == "init")
+        .flat_map(|msg| msg["body"]["node_ids"].as_array().into_iter().flatten())
+        .filter_map(Value::as_str)
+        .collect();
+    input
+        .iter()
+        .filter_map(|msg| msg["src"].as_str())
+        .filter(|src| !nodes.contains(src))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Feeds `<workload>.in.jsonl` to `dist-sys <workload>` and checks what it sends to clients against
+/// `<workload>.out.jsonl`
+fn conforms(workload: &str) {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/conformance");
+    let input = fs::read(fixtures.join(format!("{workload}.in.jsonl"))).unwrap();
+    let clients = clients(&read_lines(&fixtures.join(format!("{workload}.in.jsonl"))));
+    let expected = read_lines(&fixtures.join(format!("{workload}.out.jsonl")));
+
+    let mut node = Command::new(env!("CARGO_BIN_EXE_dist-sys"))
+        .arg(workload)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    node.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = node.wait_with_output().unwrap();
+    assert!(output.status.success(), "{workload} exited with {}", output.status);
+
+    // Messages to other nodes and services are up to the implementation, replies to clients are the protocol
+    let sent: Vec<Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let replies: Vec<&Value> = sent
+        .iter()
+        .filter(|msg| msg["dest"].as_str().is_some_and(|dest| clients.contains(dest)))
+        .collect();
+
+    for (i, (expected, actual)) in expected.iter().zip(&replies).enumerate() {
+        if let Some(difference) = mismatch(expected, actual, "") {
+            panic!("{workload} reply {i} differs at {difference}\n  expected {expected}\n  got      {actual}");
+        }
+    }
+    assert_eq!(
+        replies.len(),
+        expected.len(),
+        "{workload} sent {} replies, expected {}",
+        replies.len(),
+        expected.len()
+    );
+}
+
+#[test]
+fn echo_conforms() {
+    conforms("echo");
+}
+
+#[test]
+fn generate_conforms() {
+    conforms("generate");
+}
+
+#[test]
+fn broadcast_conforms() {
+    conforms("broadcast");
+}
+
+#[test]
+fn g_counter_conforms() {
+    conforms("g_counter");
+}
+
+#[test]
+fn kafka_conforms() {
+    conforms("kafka");
+}
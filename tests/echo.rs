@@ -0,0 +1,26 @@
+use dist_sys_challenge::{cluster::Cluster, workloads::echo::EchoWorkload};
+use serde_json::json;
+
+#[test]
+fn echoes_back_to_each_client() {
+    let cluster = Cluster::start::<EchoWorkload>(2);
+    let mut c1 = cluster.client("c1");
+    let mut c2 = cluster.client("c2");
+
+    let reply = c1.rpc("n1", json!({"type": "echo", "echo": "hello"}));
+    assert_eq!(reply["type"], "echo_ok");
+    assert_eq!(reply["echo"], "hello");
+
+    let reply = c2.rpc("n2", json!({"type": "echo", "echo": "world"}));
+    assert_eq!(reply["echo"], "world");
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let cluster = Cluster::start::<EchoWorkload>(1);
+    let mut c1 = cluster.client("c1");
+
+    let reply = c1.rpc("n1", json!({"type": "echo"}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 12);
+}
@@ -0,0 +1,86 @@
+use dist_sys_challenge::{
+    cluster::Cluster,
+    faults::{FaultScript, Latency, LinkFaults},
+    sim::{seed_from_env, Simulation},
+    workloads::{broadcast::BroadcastWorkload, g_counter::GCounterWorkload},
+};
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn read_messages(sim: &mut Simulation<BroadcastWorkload>, node: &str) -> Value {
+    sim.rpc("c2", node, json!({"type": "read"}))["messages"].clone()
+}
+
+#[test]
+fn broadcast_heals_after_a_partition() {
+    let seed = seed_from_env();
+    let mut sim = Simulation::<BroadcastWorkload>::new(4, seed);
+    let topology = json!({"n1": ["n2"], "n2": ["n1"], "n3": ["n4"], "n4": ["n3"]});
+    for node in ["n1", "n2", "n3", "n4"] {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+
+    sim.faults().partition([["n1", "n2"], ["n3", "n4"]]);
+    sim.script(FaultScript::new().at(Duration::from_secs(5), |faults| faults.heal()));
+    sim.rpc("c1", "n1", json!({"type": "broadcast", "message": 1}));
+    sim.rpc("c1", "n3", json!({"type": "broadcast", "message": 2}));
+
+    sim.run_for(Duration::from_secs(3));
+    assert_eq!(read_messages(&mut sim, "n2"), json!([1]), "seed {seed}");
+    assert_eq!(read_messages(&mut sim, "n4"), json!([2]), "seed {seed}");
+
+    sim.run_for(Duration::from_secs(5));
+    for node in ["n1", "n2", "n3", "n4"] {
+        assert_eq!(read_messages(&mut sim, node), json!([1, 2]), "{node} with seed {seed}");
+    }
+}
+
+#[test]
+fn g_counter_survives_a_lossy_network() {
+    let seed = seed_from_env();
+    let mut sim = Simulation::<GCounterWorkload>::new(5, seed);
+    sim.faults().set_default(LinkFaults {
+        drop: 0.3,
+        duplicate: 0.2,
+        reorder: 0.2,
+        latency: Latency::Exponential(Duration::from_millis(50)),
+    });
+    sim.faults().set_link(
+        "n1",
+        "n2",
+        LinkFaults {
+            drop: 1.0,
+            ..Default::default()
+        },
+    );
+
+    for (delta, node) in (1..=10).zip(["n1", "n2", "n3", "n4", "n5"].into_iter().cycle()) {
+        sim.rpc("c1", node, json!({"type": "add", "delta": delta}));
+    }
+    sim.run_for(Duration::from_secs(30));
+    for node in ["n1", "n2", "n3", "n4", "n5"] {
+        assert_eq!(
+            sim.rpc("c1", node, json!({"type": "read"}))["value"],
+            55,
+            "{node} with seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn cluster_partitions_are_scriptable() {
+    let cluster = Cluster::start::<GCounterWorkload>(2);
+    let mut c1 = cluster.client("c1");
+    cluster.faults().partition([["n1"], ["n2"]]);
+    cluster.script(FaultScript::new().at(Duration::from_secs(1), |faults| faults.heal()));
+
+    c1.rpc("n1", json!({"type": "add", "delta": 5}));
+    assert_eq!(c1.rpc("n2", json!({"type": "read"}))["value"], 0);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while c1.rpc("n2", json!({"type": "read"}))["value"] != 5 {
+        assert!(Instant::now() < deadline, "n2 never saw the add");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
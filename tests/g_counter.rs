@@ -0,0 +1,33 @@
+use dist_sys_challenge::{cluster::Cluster, workloads::g_counter::GCounterWorkload};
+use serde_json::json;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn every_node_converges_on_the_total() {
+    let cluster = Cluster::start::<GCounterWorkload>(3);
+    let mut c1 = cluster.client("c1");
+    let mut c2 = cluster.client("c2");
+
+    for (delta, node) in [(1, "n1"), (2, "n2"), (3, "n3"), (4, "n1")] {
+        let reply = c1.rpc(node, json!({"type": "add", "delta": delta}));
+        assert_eq!(reply["type"], "add_ok");
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    for node in cluster.nodes() {
+        loop {
+            let reply = c2.rpc(node, json!({"type": "read"}));
+            assert_eq!(reply["type"], "read_ok");
+            if reply["value"] == 10 {
+                break;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "{node} read {} instead of 10",
+                reply["value"]
+            );
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use dist_sys_challenge::{
+    intercept::{Interceptor, Verdict},
+    message::{Error, MsgId, RawMessage},
+    messages,
+    node::{Node, NodeId},
+    options::Options,
+    outbox::Sender,
+    sim::Simulation,
+    transport::Channel,
+    workloads::{
+        broadcast::BroadcastWorkload,
+        echo::EchoWorkload,
+        workload::{Body, Workload},
+    },
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Shouts every echo, turns away client c9 and answers reads of the `secret` itself
+struct Gatekeeper;
+
+impl Interceptor for Gatekeeper {
+    fn inbound(&mut self, mut message: RawMessage) -> Verdict {
+        if message.src == "c9" {
+            return Verdict::Drop;
+        }
+        if message.body["echo"] == "secret" {
+            return Verdict::Answer(json!({"type": "echo_ok", "echo": "[redacted]"}));
+        }
+        if let Some(echo) = message.body["echo"].as_str() {
+            message.body["echo"] = echo.to_uppercase().into();
+        }
+        Verdict::Pass(message)
+    }
+}
+
+/// Cuts a node off from the others by dropping what it sends them, and notes the order it ran in
+struct Isolate(Arc<Mutex<Vec<&'static str>>>, &'static str);
+
+impl Interceptor for Isolate {
+    fn outbound(&mut self, message: RawMessage) -> Verdict {
+        self.0.lock().unwrap().push(self.1);
+        if message.dest.starts_with('n') {
+            return Verdict::Drop;
+        }
+        Verdict::Pass(message)
+    }
+}
+
+#[test]
+fn inbound_interceptors_modify_drop_and_answer() {
+    let mut sim = Simulation::<EchoWorkload>::new(1, 1);
+    sim.intercept("n1", Gatekeeper);
+
+    let reply = sim.rpc("c1", "n1", json!({"type": "echo", "echo": "hi"}));
+    assert_eq!(reply["echo"], "HI");
+    let reply = sim.rpc("c1", "n1", json!({"type": "echo", "echo": "secret"}));
+    assert_eq!(
+        reply,
+        json!({"type": "echo_ok", "echo": "[redacted]", "in_reply_to": 1})
+    );
+
+    sim.send("c9", "n1", json!({"type": "echo", "echo": "hi"}));
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(sim.recv("c9"), None);
+    // Only what got through reached the workload
+    assert_eq!(sim.metrics("n1").handle_request.len(), 1);
+}
+
+#[test]
+fn outbound_interceptors_run_in_reverse() {
+    let mut sim = Simulation::<BroadcastWorkload>::new(2, 1);
+    let order = Arc::new(Mutex::new(Vec::new()));
+    sim.intercept("n1", Isolate(order.clone(), "first"));
+    sim.intercept("n1", Isolate(order.clone(), "second"));
+
+    let topology = json!({"n1": ["n2"], "n2": ["n1"]});
+    for node in ["n1", "n2"] {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    let reply = sim.rpc("c1", "n1", json!({"type": "broadcast", "message": 1}));
+    assert_eq!(reply["type"], "broadcast_ok");
+    sim.run_for(Duration::from_secs(3));
+
+    let read = sim.rpc("c1", "n2", json!({"type": "read"}));
+    assert_eq!(read["messages"], json!([]));
+    let order = order.lock().unwrap();
+    assert_eq!(order[..2], ["second", "first"]);
+    // Gossip never got past the interceptor registered last, while replies to the client went through both
+    let seen_by = |name| order.iter().filter(|seen| **seen == name).count();
+    assert_eq!(seen_by("first"), 2);
+    assert!(seen_by("second") > 2);
+
+    let lines: Vec<Value> = sim
+        .history()
+        .iter()
+        .map(|(_, line)| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(lines.iter().all(|line| line["src"] != "n1" || line["dest"] != "n2"));
+}
+
+/// Answers what n1 receives from c9 and every request it sends with bodies that are not objects
+struct Garble;
+
+impl Interceptor for Garble {
+    fn inbound(&mut self, message: RawMessage) -> Verdict {
+        if message.src == "c9" {
+            return Verdict::Answer(json!("nope"));
+        }
+        Verdict::Pass(message)
+    }
+
+    fn outbound(&mut self, message: RawMessage) -> Verdict {
+        if message.dest.starts_with('n') {
+            return Verdict::Answer(json!(42));
+        }
+        Verdict::Pass(message)
+    }
+}
+
+#[test]
+fn answers_that_are_not_objects_are_dropped() {
+    let mut sim = Simulation::<BroadcastWorkload>::new(2, 1);
+    sim.intercept("n1", Garble);
+
+    sim.send("c9", "n1", json!({"type": "read"}));
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(sim.recv("c9"), None);
+
+    let topology = json!({"n1": ["n2"], "n2": ["n1"]});
+    for node in ["n1", "n2"] {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    sim.rpc("c1", "n1", json!({"type": "broadcast", "message": 1}));
+    sim.run_for(Duration::from_secs(3));
+    let read = sim.rpc("c1", "n1", json!({"type": "read"}));
+    assert_eq!(read["messages"], json!([1]));
+}
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Hello,
+}
+
+/// Says hello to n2 as soon as it starts
+struct Greeter {
+    tx: Sender<Body<Self>>,
+}
+
+impl Workload for Greeter {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        let hello = Body::Request {
+            dest: "n2".into(),
+            request: Request::Hello,
+        };
+        tx.send(hello).expect("send failed");
+        Greeter { tx }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let Handle::Hello { reply } = request.incoming(reponse_factory);
+        self.tx.send(reply.with(HelloOk {})).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+/// Notes the type of every message that goes by, received ones prefixed with `<` and sent ones with `>`
+struct Witness(Arc<Mutex<Vec<String>>>);
+
+impl Interceptor for Witness {
+    fn inbound(&mut self, message: RawMessage) -> Verdict {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("< {}", message.body["type"].as_str().unwrap_or_default()));
+        Verdict::Pass(message)
+    }
+
+    fn outbound(&mut self, message: RawMessage) -> Verdict {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("> {}", message.body["type"].as_str().unwrap_or_default()));
+        Verdict::Pass(message)
+    }
+}
+
+#[test]
+fn interceptors_added_before_init_see_the_node_start() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (input_send, input) = mpsc::channel();
+    let (output, output_recv) = mpsc::channel::<String>();
+    let node = Node::<Greeter>::builder().intercept(Witness(seen.clone()));
+    let node = thread::spawn(move || node.init_with(Channel { input, output }).map(Node::run));
+
+    let init = json!({"src": "c1", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}});
+    input_send.send(init.to_string()).unwrap();
+    let timeout = Duration::from_secs(5);
+    let sent: Vec<Value> = (0..2)
+        .map(|_| serde_json::from_str(&output_recv.recv_timeout(timeout).unwrap()).unwrap())
+        .collect();
+    assert_eq!(sent[0]["body"]["type"], "init_ok");
+    assert_eq!(sent[1]["body"]["type"], "hello");
+    drop(input_send);
+    node.join().unwrap();
+
+    assert_eq!(*seen.lock().unwrap(), ["< init", "> init_ok", "> hello"]);
+}
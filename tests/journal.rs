@@ -0,0 +1,71 @@
+use dist_sys_challenge::{
+    journal::{self, Difference, Direction, Journal},
+    node::Node,
+    transport::Streams,
+    workloads::echo::EchoWorkload,
+};
+use serde_json::json;
+use std::io::{self, Cursor};
+
+const INIT: &str = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+const ECHO: &str = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"hi"}}"#;
+
+fn record(path: &std::path::Path) -> Vec<journal::Entry> {
+    let journal = Journal::create(path).expect("create journal");
+    let input = Cursor::new(format!("{INIT}\n{ECHO}\n"));
+    Node::<EchoWorkload>::init_recorded(Streams(input, io::sink()), Some(journal))
+        .expect("initialized")
+        .run();
+    journal::read(path).expect("read journal")
+}
+
+#[test]
+fn a_journal_replays_without_differences() {
+    let path = std::env::temp_dir().join(format!("journal-replays-{}.jsonl", std::process::id()));
+    let recorded = record(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let directions: Vec<_> = recorded.iter().map(|entry| entry.direction).collect();
+    let (received, sent) = (Direction::Received, Direction::Sent);
+    assert_eq!(directions, [received, sent, received, sent]);
+    assert_eq!(recorded[3].body["type"], "echo_ok");
+
+    let replayed = journal::replay::<EchoWorkload>(&recorded, true).expect("initialized");
+    assert_eq!(replayed.len(), 2);
+    assert_eq!(journal::diff(&recorded, &replayed), []);
+}
+
+#[test]
+fn replaying_reports_what_changed() {
+    let path = std::env::temp_dir().join(format!("journal-differs-{}.jsonl", std::process::id()));
+    let mut recorded = record(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let original = recorded[3].body.clone();
+    recorded[3].body["echo"] = json!("bye");
+    let replayed = journal::replay::<EchoWorkload>(&recorded, true).expect("initialized");
+    let tampered = recorded[3].body.clone();
+    assert_eq!(
+        journal::diff(&recorded, &replayed),
+        [
+            Difference::Missing {
+                dest: "c1".into(),
+                body: tampered
+            },
+            Difference::Extra {
+                dest: "c1".into(),
+                body: original
+            },
+        ]
+    );
+}
+
+#[test]
+fn a_journal_without_init_does_not_replay() {
+    let path = std::env::temp_dir().join(format!("journal-no-init-{}.jsonl", std::process::id()));
+    let recorded = record(&path);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(journal::replay::<EchoWorkload>(&[], true), None);
+    assert_eq!(journal::replay::<EchoWorkload>(&recorded[2..], true), None);
+}
@@ -0,0 +1,56 @@
+use dist_sys_challenge::{sim::Simulation, workloads::kafka::KafkaWorkload};
+use serde_json::{json, Value};
+
+mod common;
+
+/// A node with two messages in log `k1`
+fn started() -> Simulation<KafkaWorkload> {
+    let mut sim = common::steady(1, 7);
+    for msg in [1, 2] {
+        sim.rpc("c1", "n1", json!({"type": "send", "key": "k1", "msg": msg}));
+    }
+    sim
+}
+
+fn commit(sim: &mut Simulation<KafkaWorkload>, offsets: Value) -> Value {
+    sim.rpc("c1", "n1", json!({"type": "commit_offsets", "offsets": offsets}))
+}
+
+fn committed(sim: &mut Simulation<KafkaWorkload>) -> Value {
+    sim.rpc(
+        "c1",
+        "n1",
+        json!({"type": "list_committed_offsets", "keys": ["k1", "k2"]}),
+    )["offsets"]
+        .clone()
+}
+
+#[test]
+fn offsets_without_messages_are_not_committed() {
+    let mut sim = started();
+
+    let reply = commit(&mut sim, json!({"k1": 0, "k2": 0}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 20);
+    let reply = commit(&mut sim, json!({"k1": 2}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 22);
+
+    // Neither request committed anything, not even the offset of k1 that was fine
+    assert_eq!(committed(&mut sim), json!({}));
+}
+
+#[test]
+fn committed_offsets_never_go_back() {
+    let mut sim = started();
+    assert_eq!(commit(&mut sim, json!({"k1": 1}))["type"], "commit_offsets_ok");
+
+    let reply = commit(&mut sim, json!({"k1": 0}));
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["code"], 22);
+    assert_eq!(committed(&mut sim), json!({"k1": 1}));
+
+    // Committing the same offset again is fine
+    assert_eq!(commit(&mut sim, json!({"k1": 1}))["type"], "commit_offsets_ok");
+    assert_eq!(committed(&mut sim), json!({"k1": 1}));
+}
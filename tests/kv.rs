@@ -0,0 +1,115 @@
+use dist_sys_challenge::{
+    kv::KvClient,
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    sim::Simulation,
+    workloads::workload::{Body, Workload},
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+
+mod common;
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Read { key: String },
+    #[ok]
+    Cas { key: String, from: u64, to: u64 },
+    #[ok(outcomes: Vec<Value>)]
+    Outcomes,
+}
+
+/// Passes reads and compare-and-sets on to `seq-kv`, and notes what each came back with
+struct Store {
+    tx: Sender<Body<Self>>,
+    kv: KvClient<String, u64>,
+    outcomes: Vec<Value>,
+}
+
+impl Workload for Store {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Store {
+            tx,
+            kv: KvClient::seq(),
+            outcomes: Vec::new(),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Read { key, reply } => {
+                let read = self.kv.read(key.clone(), |store: &mut Store, result| {
+                    store.outcomes.push(common::outcome(result))
+                });
+                self.tx.send(read).expect("send failed");
+                reply.with(ReadOk {})
+            }
+            Handle::Cas { key, from, to, reply } => {
+                let cas = self
+                    .kv
+                    .cas(key.clone(), *from, *to, false, |store: &mut Store, result| {
+                        store.outcomes.push(common::outcome(result))
+                    });
+                self.tx.send(cas).expect("send failed");
+                reply.with(CasOk {})
+            }
+            Handle::Outcomes { reply } => reply.with(OutcomesOk {
+                outcomes: self.outcomes.clone(),
+            }),
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+/// Has n1 send `request` to `seq-kv`, and answers it with `reply`
+fn exchange(sim: &mut Simulation<Store>, request: Value, mut reply: Value) {
+    let kind = request["type"].clone();
+    sim.rpc("c1", "n1", request);
+    sim.run_for(Duration::from_millis(100));
+    let (src, sent) = sim.recv("seq-kv").expect("nothing sent to seq-kv");
+    assert_eq!(src, "n1");
+    assert_eq!(sent["type"], kind);
+    assert_eq!(sent["key"], "x");
+    reply["in_reply_to"] = sent["msg_id"].clone();
+    sim.send("seq-kv", "n1", reply);
+    sim.run_for(Duration::from_millis(100));
+}
+
+#[test]
+fn service_errors_map_to_outcomes() {
+    let mut sim = common::steady::<Store>(1, 1);
+    let read = json!({"type": "read", "key": "x"});
+    let cas = json!({"type": "cas", "key": "x", "from": 1, "to": 2});
+
+    exchange(&mut sim, read.clone(), json!({"type": "read_ok", "value": 1}));
+    exchange(&mut sim, read.clone(), json!({"type": "error", "code": 20}));
+    exchange(&mut sim, read, json!({"type": "error", "code": 11}));
+    exchange(&mut sim, cas.clone(), json!({"type": "cas_ok"}));
+    exchange(&mut sim, cas.clone(), json!({"type": "error", "code": 22}));
+    exchange(&mut sim, cas.clone(), json!({"type": "error", "code": 20}));
+    exchange(&mut sim, cas, json!({"type": "error", "code": 11}));
+
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    // A missing key reads as null and a failed precondition is a cas that didn't happen, anything else fails
+    assert_eq!(
+        reply["outcomes"],
+        json!([1, null, {"code": 11}, true, false, false, {"code": 11}])
+    );
+}
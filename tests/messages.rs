@@ -0,0 +1,111 @@
+use dist_sys_challenge::{
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::{self, Sender},
+    workloads::workload::{Body, Workload},
+};
+use serde_json::json;
+use std::collections::HashSet;
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok(total: u64)]
+    Add { delta: u64 },
+    #[ok]
+    Reset,
+    #[serde(rename = "sync")]
+    SyncState { total: u64 },
+}
+
+struct Counter {
+    tx: Sender<Body<Self>>,
+    total: u64,
+}
+
+impl Workload for Counter {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Counter { tx, total: 0 }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Add { delta, reply } => {
+                self.total += delta;
+                reply.with(AddOk { total: self.total })
+            }
+            Handle::Reset { reply } => {
+                self.total = 0;
+                reply.with(ResetOk {})
+            }
+            Handle::SyncState { total } => {
+                self.total = self.total.max(*total);
+                return Ok(());
+            }
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+#[test]
+fn requests_and_responses_are_tagged_in_snake_case() {
+    let request: Request = serde_json::from_value(json!({"type": "add", "delta": 2})).unwrap();
+    assert!(matches!(request, Request::Add { delta: 2 }));
+    let request: Request = serde_json::from_value(json!({"type": "sync", "total": 3})).unwrap();
+    assert!(matches!(request, Request::SyncState { total: 3 }));
+
+    let reply = serde_json::to_value(Reply::from(AddOk { total: 5 })).unwrap();
+    assert_eq!(reply, json!({"type": "add_ok", "total": 5}));
+    let reply = serde_json::to_value(Reply::from(ResetOk {})).unwrap();
+    assert_eq!(reply, json!({"type": "reset_ok"}));
+    let reply: Reply = serde_json::from_value(json!({"type": "add_ok", "total": 1})).unwrap();
+    assert!(matches!(reply, Reply::AddOk(AddOk { total: 1 })));
+
+    assert_eq!(Request::TYPES, ["add", "reset", "sync"]);
+    assert_eq!(Reply::TYPES, ["add_ok", "reset_ok"]);
+}
+
+#[test]
+fn a_request_is_answered_with_its_own_response() {
+    let (tx, _rx) = outbox::channel();
+    let mut counter = Counter::new("n1".into(), HashSet::new(), tx, &Options::default());
+    let src = "c1".to_string();
+    let factory = |response| Body::<Counter>::Response {
+        dest: src.clone(),
+        in_reply_to: 7,
+        response,
+    };
+
+    let request = Request::Add { delta: 4 };
+    let Handle::Add { delta, reply } = request.incoming(factory) else {
+        panic!("an add request pairs with an add reply");
+    };
+    assert_eq!(*delta, 4);
+    let Body::Response {
+        dest,
+        in_reply_to,
+        response: Reply::AddOk(AddOk { total }),
+    } = reply.with(AddOk { total: 4 })
+    else {
+        panic!("expected an add_ok response");
+    };
+    assert_eq!((dest.as_str(), in_reply_to, total), ("c1", 7, 4));
+
+    counter
+        .handle_request(&Request::SyncState { total: 9 }, &src, |_| unreachable!())
+        .unwrap();
+    assert_eq!(counter.total, 9);
+}
@@ -0,0 +1,46 @@
+use dist_sys_challenge::{
+    cluster::Cluster,
+    sim::Simulation,
+    workloads::{broadcast::BroadcastWorkload, echo::EchoWorkload},
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+#[test]
+fn nodes_answer_metrics_requests() {
+    let cluster = Cluster::start::<EchoWorkload>(1);
+    let mut c1 = cluster.client("c1");
+    for _ in 0..3 {
+        let reply = c1.rpc("n1", json!({"type": "echo", "echo": "hello"}));
+        assert_eq!(reply["type"], "echo_ok");
+    }
+
+    let reply = c1.rpc("n1", json!({"type": "metrics"}));
+    assert_eq!(reply["type"], "metrics_ok");
+    assert_eq!(reply["received"]["by_type"]["echo"], 3);
+    assert_eq!(reply["received"]["by_peer"]["c1"], 4);
+    assert_eq!(reply["sent"]["by_type"]["echo_ok"], 3);
+    assert!(reply["sent"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(reply["client_requests"], 3);
+    assert_eq!(reply["handle_request"]["echo"]["count"], 3);
+}
+
+#[test]
+fn msgs_per_op_counts_messages_between_nodes() {
+    let mut sim = Simulation::<BroadcastWorkload>::new(3, 7);
+    let nodes: Vec<_> = sim.nodes().cloned().collect();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    for value in 0..10 {
+        sim.rpc("c1", "n1", json!({"type": "broadcast", "message": value}));
+    }
+    sim.run_for(Duration::from_secs(5));
+
+    let metrics = sim.metrics("n1");
+    assert_eq!(metrics.client_requests, 11);
+    let expected = metrics.sent_to_nodes() as f64 / 11.0;
+    assert_eq!(metrics.msgs_per_op(), Some(expected));
+    assert!(metrics.sent_to_nodes() > 0);
+}
@@ -0,0 +1,237 @@
+use dist_sys_challenge::{
+    intercept::{Interceptor, Verdict},
+    message::{Error, MsgId, RawMessage},
+    messages,
+    node::{Node, NodeId},
+    options::Options,
+    outbox::{Class, Sender, OUTBOX_CAPACITY},
+    transport::Streams,
+    workloads::{
+        broadcast::{BroadcastWorkload, Request},
+        echo::EchoWorkload,
+        multi::{Composite, Tagged},
+        workload::{Body, Workload},
+    },
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, Cursor, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// An output that stands still, like a consumer that stopped reading, until it is opened
+#[derive(Clone, Default)]
+struct Gated {
+    open: Arc<(Mutex<bool>, Condvar)>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Gated {
+    fn open(&self) {
+        let (open, changed) = &*self.open;
+        *open.lock().unwrap() = true;
+        changed.notify_all();
+    }
+}
+
+impl Write for Gated {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let (open, changed) = &*self.open;
+        let _open = changed.wait_while(open.lock().unwrap(), |open| !*open).unwrap();
+        self.written.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Broadcasts 50 values to n1 of five nodes while nothing it writes gets out, and checks that every client got
+/// its reply while most of the gossip was dropped
+fn drops_gossip_but_not_replies<W: Workload + Send + 'static>() {
+    let nodes: Vec<String> = (1..=5).map(|i| format!("n{i}")).collect();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    let mut input =
+        vec![json!({"src": "c1", "dest": "n1", "body": {"type": "topology", "msg_id": 1, "topology": topology}})];
+    for value in 0..50 {
+        input.push(
+            json!({"src": "c1", "dest": "n1", "body": {"type": "broadcast", "msg_id": value + 2, "message": value}}),
+        );
+    }
+    let input: String = input.iter().map(|msg| format!("{msg}\n")).collect();
+
+    let output = Gated::default();
+    let transport = Streams(Cursor::new(input), output.clone());
+    let all_nodes: HashSet<_> = nodes.iter().cloned().collect();
+    let node = Node::<W>::builder().options(Options::default().with(&OUTBOX_CAPACITY, "8"));
+    let node = thread::spawn(move || node.start_with("n1".into(), all_nodes, transport).run());
+    thread::sleep(Duration::from_millis(200));
+    output.open();
+    node.join().unwrap();
+
+    let written = String::from_utf8(output.written.lock().unwrap().clone()).unwrap();
+    let bodies: Vec<Value> = written
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["body"].clone())
+        .collect();
+    let replies: BTreeSet<u64> = bodies
+        .iter()
+        .filter(|body| body["type"] == "broadcast_ok" || body["type"] == "topology_ok")
+        .map(|body| body["in_reply_to"].as_u64().unwrap())
+        .collect();
+    assert_eq!(replies, (1..=51).collect());
+    // Every broadcast gossips to four nodes, most of which had to be dropped while the output stood still
+    let gossip = bodies.iter().filter(|body| body["type"] == "gossip").count();
+    assert!(gossip < 200, "{gossip} gossip messages written");
+}
+
+#[test]
+fn full_outbox_drops_gossip_but_not_replies() {
+    drops_gossip_but_not_replies::<BroadcastWorkload>();
+
+    assert_eq!(
+        BroadcastWorkload::class(&Request::Gossip {
+            values: BTreeSet::new()
+        }),
+        Class::Gossip
+    );
+    assert_eq!(BroadcastWorkload::class(&Request::Read), Class::Protocol);
+}
+
+#[test]
+fn a_composite_keeps_the_class_of_its_components() {
+    type Combined = Composite<(EchoWorkload, BroadcastWorkload)>;
+    drops_gossip_but_not_replies::<Combined>();
+
+    let gossip = Tagged(json!({"type": "gossip", "messages": [1]}));
+    assert_eq!(Combined::class(&gossip), Class::Gossip);
+    let echo = Tagged(json!({"type": "echo", "echo": "hi"}));
+    assert_eq!(Combined::class(&echo), Class::Protocol);
+}
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Chat {
+    #[ok]
+    Chat {
+        count: u64,
+    },
+    Rumor {
+        n: u64,
+    },
+    Note {
+        n: u64,
+    },
+}
+
+/// Sends `count` messages to n2 on request, alternating between gossip and plain requests
+struct Chatter {
+    tx: Sender<Body<Self>>,
+}
+
+impl Workload for Chatter {
+    type Request = Chat;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Chatter { tx }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Chat,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let Handle::Chat { count, reply } = request.incoming(reponse_factory) else {
+            return Ok(());
+        };
+        for n in 0..*count {
+            let request = if n % 2 == 0 {
+                Chat::Rumor { n }
+            } else {
+                Chat::Note { n }
+            };
+            let dest = "n2".to_string();
+            self.tx.send(Body::Request { dest, request }).expect("send failed");
+        }
+        self.tx.send(reply.with(ChatOk {})).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+
+    fn class(request: &Chat) -> Class {
+        match request {
+            Chat::Rumor { .. } => Class::Gossip,
+            _ => Class::Protocol,
+        }
+    }
+}
+
+/// Counts the messages the node got to handling
+struct Handled(Arc<AtomicUsize>);
+
+impl Interceptor for Handled {
+    fn inbound(&mut self, message: RawMessage) -> Verdict {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Verdict::Pass(message)
+    }
+}
+
+/// Runs n1 of n1 and n2 on `input` with `options`, while nothing it writes gets out for a while. Returns the bodies
+/// written and how many messages the node had handled by the time the output opened.
+fn run_stuck(input: &[Value], options: Options) -> (Vec<Value>, usize) {
+    let input: String = input
+        .iter()
+        .map(|body| format!("{}\n", json!({"src": "c1", "dest": "n1", "body": body})))
+        .collect();
+    let output = Gated::default();
+    let transport = Streams(Cursor::new(input), output.clone());
+    let handled = Arc::new(AtomicUsize::new(0));
+    let node = Node::<Chatter>::builder()
+        .options(options)
+        .intercept(Handled(handled.clone()));
+    let all_nodes = HashSet::from(["n1".to_string(), "n2".to_string()]);
+    let node = thread::spawn(move || node.start_with("n1".into(), all_nodes, transport).run());
+    thread::sleep(Duration::from_millis(200));
+    let handled_while_stuck = handled.load(Ordering::SeqCst);
+    output.open();
+    node.join().unwrap();
+
+    let written = String::from_utf8(output.written.lock().unwrap().clone()).unwrap();
+    let bodies = written
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["body"].clone())
+        .collect();
+    (bodies, handled_while_stuck)
+}
+
+#[test]
+fn replies_get_into_a_full_outbox() {
+    let input: Vec<Value> = (1..=20)
+        .map(|msg_id| json!({"type": "chat", "msg_id": msg_id, "count": 0}))
+        .collect();
+    let (bodies, handled) = run_stuck(&input, Options::default().with(&OUTBOX_CAPACITY, "2"));
+
+    // Waiting for room in the outbox would have stopped the node a few replies in
+    assert_eq!(handled, 20);
+    assert_eq!(bodies.len(), 20);
+}
+
+#[test]
+fn messages_to_a_node_keep_their_order_whatever_their_class() {
+    let (bodies, _) = run_stuck(&[json!({"type": "chat", "msg_id": 1, "count": 20})], Options::default());
+
+    let sent: Vec<u64> = bodies
+        .iter()
+        .filter(|body| body["type"] != "chat_ok")
+        .map(|body| body["n"].as_u64().unwrap())
+        .collect();
+    assert_eq!(sent, (0..20).collect::<Vec<_>>());
+    // The reply to the client still jumped ahead of what was queued for n2
+    let reply = bodies.iter().position(|body| body["type"] == "chat_ok").unwrap();
+    assert!(reply < 2, "replied after {reply} other messages");
+}
@@ -0,0 +1,118 @@
+use dist_sys_challenge::{
+    node::Serve,
+    options::parse_duration,
+    registry::{self, Invocation},
+};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn picks_the_workload_and_its_flags() {
+    let invocation = Invocation::parse(
+        &args(&["broadcast", "--gossip-interval", "200ms", "--gossip-fanout=2"]),
+        None,
+    )
+    .unwrap();
+    assert_eq!(invocation.workload.name, "broadcast");
+    let flags: Vec<(&str, &str)> = invocation
+        .flags
+        .iter()
+        .map(|(flag, value)| (flag.name, value.as_str()))
+        .collect();
+    assert_eq!(flags, [("gossip-interval", "200ms"), ("gossip-fanout", "2")]);
+    assert!(matches!(invocation.serve, Serve::Stdio));
+
+    let invocation = Invocation::parse(&args(&["--tcp", "cluster.json", "n1"]), Some("kafka")).unwrap();
+    assert_eq!(invocation.workload.name, "kafka");
+    assert!(matches!(invocation.serve, Serve::Tcp { ref id, .. } if id == "n1"));
+
+    // An argument wins over the environment
+    let invocation = Invocation::parse(&args(&["echo"]), Some("kafka")).unwrap();
+    assert_eq!(invocation.workload.name, "echo");
+
+    assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+    assert_eq!(parse_duration("250"), Ok(Duration::from_millis(250)));
+}
+
+#[test]
+fn rejects_what_the_workload_does_not_take() {
+    for bad in [
+        &[][..],
+        &["paxos"],
+        &["echo", "--gossip-interval", "1s"],
+        &["broadcast", "--gossip-interval", "soon"],
+        &["broadcast", "--gossip-fanout"],
+        &["g_counter", "extra"],
+    ] {
+        assert!(Invocation::parse(&args(bad), None).is_err(), "{bad:?} was accepted");
+    }
+
+    let usage = registry::usage();
+    for workload in registry::WORKLOADS {
+        assert!(usage.contains(workload.name));
+    }
+    assert!(usage.contains("--sync-interval"));
+}
+
+#[test]
+fn binary_runs_the_named_workload() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dist-sys"))
+        .arg("echo")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(
+        stdin,
+        r#"{{"src":"c0","dest":"n1","body":{{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}}}"#
+    )
+    .unwrap();
+    writeln!(
+        stdin,
+        r#"{{"src":"c1","dest":"n1","body":{{"type":"echo","msg_id":2,"echo":"hi"}}}}"#
+    )
+    .unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let replies: Vec<serde_json::Value> = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(replies[0]["body"]["type"], "init_ok");
+    assert_eq!(replies[1]["body"]["type"], "echo_ok");
+    assert_eq!(replies[1]["body"]["echo"], "hi");
+}
+
+#[test]
+fn binary_logs_metrics_while_running() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dist-sys"))
+        .args(["echo", "--metrics-interval", "50ms"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(
+        stdin,
+        r#"{{"src":"c0","dest":"n1","body":{{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}}}"#
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let logs = String::from_utf8(output.stderr).unwrap();
+    let summaries = logs.lines().filter(|line| line.contains("msgs_per_op")).count();
+    // At least one while idle, and the last at shutdown
+    assert!(summaries >= 2, "{logs}");
+}
@@ -0,0 +1,203 @@
+use dist_sys_challenge::{
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    rpc::RetryPolicy,
+    sim::Simulation,
+    workloads::{
+        echo::EchoWorkload,
+        multi::{Component, Composite},
+        workload::{Body, Workload},
+    },
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+
+mod common;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_millis(150);
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Call { dest: NodeId },
+    #[ok]
+    Ask { dest: NodeId },
+    #[ok(answer: u64)]
+    Question,
+    #[ok(outcomes: Vec<Value>)]
+    Outcomes,
+}
+
+/// Pings `dest` with five attempts on request, or asks it a question of its own message types, and notes what
+/// each came back with
+struct Caller {
+    tx: Sender<Body<Self>>,
+    outcomes: Vec<Value>,
+}
+
+impl Workload for Caller {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = ();
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Caller {
+            tx,
+            outcomes: Vec::new(),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let response = match request.incoming(reponse_factory) {
+            Handle::Call { dest, reply } => {
+                let retry = RetryPolicy::new(TIMEOUT, 5).with_backoff(INITIAL_BACKOFF, MAX_BACKOFF);
+                let ping = Body::call(
+                    dest.clone(),
+                    &json!({"type": "ping"}),
+                    Some(retry),
+                    |caller: &mut Self, reply: Result<Value, Error>, _: &NodeId| {
+                        caller.outcomes.push(common::outcome(reply))
+                    },
+                );
+                self.tx.send(ping).expect("send failed");
+                reply.with(CallOk {})
+            }
+            Handle::Ask { dest, reply } => {
+                let question = Body::rpc(
+                    dest.clone(),
+                    Request::Question,
+                    |caller: &mut Self, reply: Result<Reply, Error>, _: &NodeId| {
+                        caller.outcomes.push(common::outcome(reply))
+                    },
+                );
+                self.tx.send(question).expect("send failed");
+                reply.with(AskOk {})
+            }
+            Handle::Question { reply } => reply.with(QuestionOk { answer: 42 }),
+            Handle::Outcomes { reply } => reply.with(OutcomesOk {
+                outcomes: self.outcomes.clone(),
+            }),
+        };
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+}
+
+impl Component for Caller {
+    const REQUEST_TYPES: &'static [&'static str] = Request::TYPES;
+}
+
+/// When every ping to `svc` arrived, in a steady simulation so that the gaps are the node's own
+fn pings(sim: &Simulation<Caller>) -> Vec<Duration> {
+    common::delivered(sim)
+        .into_iter()
+        .filter(|(_, msg)| msg["dest"] == "svc")
+        .map(|(at, _)| at)
+        .collect()
+}
+
+#[test]
+fn unanswered_requests_back_off_and_time_out() {
+    let mut sim = common::steady(1, 7);
+    sim.rpc("c1", "n1", json!({"type": "call", "dest": "svc"}));
+    sim.run_for(Duration::from_secs(5));
+
+    let pings = pings(&sim);
+    assert_eq!(pings.len(), 5);
+    for (attempt, gap) in pings.windows(2).map(|pair| pair[1] - pair[0]).enumerate() {
+        // Each attempt times out, then the backoff doubles up to its cap with up to half of it taken off
+        let backoff = (INITIAL_BACKOFF * 2u32.pow(attempt as u32)).min(MAX_BACKOFF);
+        assert!(
+            gap >= TIMEOUT + backoff / 2,
+            "gap {gap:?} after attempt {}",
+            attempt + 1
+        );
+        assert!(gap <= TIMEOUT + backoff, "gap {gap:?} after attempt {}", attempt + 1);
+    }
+
+    // The callback ran once, with a timeout
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    assert_eq!(reply["outcomes"], json!([{"code": 0}]));
+}
+
+#[test]
+fn answered_requests_are_not_resent() {
+    let mut sim = common::steady(1, 7);
+    sim.rpc("c1", "n1", json!({"type": "call", "dest": "svc"}));
+    sim.run_for(Duration::from_millis(200));
+
+    let (_, ping) = sim.recv("svc").unwrap();
+    let (_, resent) = sim.recv("svc").unwrap();
+    assert_eq!(ping, resent);
+    sim.send("svc", "n1", json!({"type": "pong", "in_reply_to": ping["msg_id"]}));
+    sim.run_for(Duration::from_secs(5));
+
+    assert_eq!(pings(&sim).len(), 2);
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    assert_eq!(reply["outcomes"][0]["type"], "pong");
+    assert_eq!(reply["outcomes"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn typed_rpcs_decode_the_response() {
+    let mut sim = common::steady::<Caller>(2, 7);
+    sim.rpc("c1", "n1", json!({"type": "ask", "dest": "n2"}));
+    sim.run_for(Duration::from_millis(100));
+
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    assert_eq!(reply["outcomes"], json!([{"type": "question_ok", "answer": 42}]));
+}
+
+/// Has n1 ask `svc` a question, and answers it with `answer`
+fn answer<W: Workload + 'static>(sim: &mut Simulation<W>, mut answer: Value) {
+    sim.rpc("c1", "n1", json!({"type": "ask", "dest": "svc"}));
+    sim.run_for(Duration::from_millis(100));
+    let (_, question) = sim.recv("svc").expect("nothing sent to svc");
+    assert_eq!(question["type"], "question");
+    answer["in_reply_to"] = question["msg_id"].clone();
+    sim.send("svc", "n1", answer);
+    sim.run_for(Duration::from_millis(100));
+}
+
+#[test]
+fn typed_rpcs_hand_errors_and_undecodable_replies_to_the_callback() {
+    let mut sim = common::steady::<Caller>(1, 7);
+    answer(&mut sim, json!({"type": "error", "code": 11, "text": "try again"}));
+    answer(&mut sim, json!({"type": "question_ok", "answer": "many"}));
+    answer(&mut sim, json!({"type": "question_ok", "answer": 7}));
+
+    // A reply that is not the response the request is paired with fails as malformed
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    assert_eq!(
+        reply["outcomes"],
+        json!([{"code": 11}, {"code": 12}, {"type": "question_ok", "answer": 7}])
+    );
+}
+
+#[test]
+fn typed_rpcs_of_a_component_come_back_to_it() {
+    let mut sim = common::steady::<Composite<(EchoWorkload, Caller)>>(2, 7);
+    sim.rpc("c1", "n1", json!({"type": "ask", "dest": "n2"}));
+    sim.run_for(Duration::from_millis(100));
+    answer(&mut sim, json!({"type": "error", "code": 11, "text": "try again"}));
+    answer(&mut sim, json!({"type": "question_ok", "answer": "many"}));
+
+    let reply = sim.rpc("c1", "n1", json!({"type": "outcomes"}));
+    assert_eq!(
+        reply["outcomes"],
+        json!([{"type": "question_ok", "answer": 42}, {"code": 11}, {"code": 12}])
+    );
+}
@@ -0,0 +1,57 @@
+use dist_sys_challenge::{
+    registry,
+    schema::{self, Schemas},
+    workloads::broadcast::BroadcastWorkload,
+};
+use serde_json::{json, Value};
+
+/// The object schema of the variant of a tagged enum whose `type` is `kind`
+fn variant(schema: &Value, kind: &str) -> Value {
+    schema["oneOf"]
+        .as_array()
+        .expect("a tagged enum")
+        .iter()
+        .find(|variant| variant["properties"]["type"]["enum"] == json!([kind]))
+        .unwrap_or_else(|| panic!("no variant {kind}"))
+        .clone()
+}
+
+#[test]
+fn schemas_use_the_names_on_the_wire() {
+    let schemas = Schemas::of::<BroadcastWorkload>();
+    let request = serde_json::to_value(&schemas.request).unwrap();
+    let response = serde_json::to_value(&schemas.response).unwrap();
+
+    assert_eq!(variant(&request, "broadcast")["required"], json!(["message", "type"]));
+    assert_eq!(variant(&request, "gossip")["required"], json!(["messages", "type"]));
+    let read_ok = variant(&response, "read_ok");
+    assert_eq!(read_ok["required"], json!(["messages", "type"]));
+    assert!(read_ok["properties"].get("values").is_none());
+
+    let envelope = serde_json::to_value(schema::envelope()).unwrap();
+    assert_eq!(envelope["required"], json!(["body", "dest", "src"]));
+}
+
+#[test]
+fn writes_every_workload_and_the_envelope() {
+    let dir = std::env::temp_dir().join(format!("dist-sys-schemas-{}", std::process::id()));
+    let written = schema::write_all(&dir).unwrap();
+
+    let names: Vec<String> = written
+        .iter()
+        .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    for workload in registry::WORKLOADS.iter().map(|workload| workload.name).chain(["init"]) {
+        for kind in ["request", "response", "message"] {
+            assert!(
+                names.contains(&format!("{workload}.{kind}.json")),
+                "{workload}.{kind}.json missing"
+            );
+        }
+    }
+    assert!(names.contains(&"message.json".to_string()));
+
+    let init: Value = serde_json::from_str(&std::fs::read_to_string(dir.join("init.request.json")).unwrap()).unwrap();
+    assert_eq!(init["properties"]["type"]["enum"], json!(["init"]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
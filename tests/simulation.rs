@@ -0,0 +1,88 @@
+use dist_sys_challenge::{
+    options::Options,
+    sim::{seed_from_env, Simulation},
+    workloads::{
+        broadcast::{BroadcastWorkload, GOSSIP_FANOUT},
+        g_counter::GCounterWorkload,
+    },
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+fn broadcast_run(seed: u64) -> Simulation<BroadcastWorkload> {
+    let mut sim = Simulation::<BroadcastWorkload>::new(5, seed);
+    let nodes: Vec<_> = sim.nodes().cloned().collect();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    for (value, node) in (0..20).zip(nodes.iter().cycle()) {
+        sim.send("c1", node, json!({"type": "broadcast", "message": value}));
+    }
+    sim.run_for(Duration::from_secs(5));
+    sim
+}
+
+#[test]
+fn broadcast_converges() {
+    let seed = seed_from_env();
+    let mut sim = broadcast_run(seed);
+    let nodes: Vec<_> = sim.nodes().cloned().collect();
+    for node in &nodes {
+        let reply = sim.rpc("c2", node, json!({"type": "read"}));
+        assert_eq!(
+            reply["messages"],
+            json!((0..20).collect::<Vec<_>>()),
+            "{node} with seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn a_seed_replays_exactly() {
+    let seed = seed_from_env();
+    let first = broadcast_run(seed);
+    let second = broadcast_run(seed);
+    assert!(!first.history().is_empty());
+    assert_eq!(first.history(), second.history());
+}
+
+#[test]
+fn g_counter_converges_in_virtual_time() {
+    let seed = seed_from_env();
+    let mut sim = Simulation::<GCounterWorkload>::new(3, seed).with_latency(Duration::ZERO, Duration::from_secs(1));
+    for (delta, node) in [(1, "n1"), (2, "n2"), (3, "n3"), (4, "n1")] {
+        assert_eq!(
+            sim.rpc("c1", node, json!({"type": "add", "delta": delta}))["type"],
+            "add_ok"
+        );
+    }
+    sim.run_for(Duration::from_secs(30));
+    for node in ["n1", "n2", "n3"] {
+        assert_eq!(
+            sim.rpc("c1", node, json!({"type": "read"}))["value"],
+            10,
+            "{node} with seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn each_node_starts_with_its_own_options() {
+    // n1 gossips to nobody, so only what the others were sent spreads
+    let mut sim = Simulation::<BroadcastWorkload>::new_with_options(3, 5, |id| match id {
+        "n1" => Options::default().with(&GOSSIP_FANOUT, "0"),
+        _ => Options::default(),
+    });
+    let nodes: Vec<_> = sim.nodes().cloned().collect();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        sim.rpc("c1", node, json!({"type": "topology", "topology": topology}));
+    }
+    sim.send("c1", "n1", json!({"type": "broadcast", "message": 1}));
+    sim.send("c1", "n2", json!({"type": "broadcast", "message": 2}));
+    sim.run_for(Duration::from_secs(5));
+
+    assert_eq!(sim.rpc("c1", "n1", json!({"type": "read"}))["messages"], json!([1, 2]));
+    assert_eq!(sim.rpc("c1", "n3", json!({"type": "read"}))["messages"], json!([2]));
+}
@@ -0,0 +1,96 @@
+use dist_sys_challenge::{
+    node::Node,
+    tcp::{ClusterConfig, Tcp},
+    workloads::g_counter::GCounterWorkload,
+};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Addresses on ports that were free a moment ago
+fn free_addrs(n: usize) -> Vec<String> {
+    let listeners: Vec<_> = (0..n).map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+    listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap().to_string())
+        .collect()
+}
+
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    next_msg_id: u64,
+}
+
+impl Client {
+    fn connect(addr: &str) -> Self {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        Client {
+            stream,
+            reader,
+            next_msg_id: 0,
+        }
+    }
+
+    fn rpc(&mut self, dest: &str, mut body: Value) -> Value {
+        self.next_msg_id += 1;
+        body["msg_id"] = self.next_msg_id.into();
+        writeln!(self.stream, "{}", json!({"src": "c1", "dest": dest, "body": body})).unwrap();
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        let reply: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(reply["body"]["in_reply_to"], self.next_msg_id);
+        reply["body"].clone()
+    }
+}
+
+#[test]
+fn counter_runs_over_tcp() {
+    let addrs = free_addrs(2);
+    let config: ClusterConfig = serde_json::from_value(json!({"n1": addrs[0], "n2": addrs[1]})).unwrap();
+    for id in ["n1", "n2"] {
+        let transport = Tcp::bind(id.to_string(), config.clone()).unwrap();
+        let all_nodes = config.nodes.keys().cloned().collect();
+        thread::spawn(move || Node::<GCounterWorkload>::start_with(id.to_string(), all_nodes, transport).run());
+    }
+
+    let mut n1 = Client::connect(&addrs[0]);
+    let mut n2 = Client::connect(&addrs[1]);
+    assert_eq!(n1.rpc("n1", json!({"type": "add", "delta": 3}))["type"], "add_ok");
+    assert_eq!(n2.rpc("n2", json!({"type": "add", "delta": 4}))["type"], "add_ok");
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    for (client, node) in [(&mut n1, "n1"), (&mut n2, "n2")] {
+        while client.rpc(node, json!({"type": "read"}))["value"] != 7 {
+            assert!(Instant::now() < deadline, "the nodes did not converge");
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[test]
+fn a_connection_cannot_claim_to_be_a_node() {
+    let addrs = free_addrs(2);
+    let config: ClusterConfig = serde_json::from_value(json!({"n1": addrs[0], "n2": addrs[1]})).unwrap();
+    let transport = Tcp::bind("n1".to_string(), config.clone()).unwrap();
+    let all_nodes = config.nodes.keys().cloned().collect();
+    thread::spawn(move || Node::<GCounterWorkload>::start_with("n1".to_string(), all_nodes, transport).run());
+
+    // The reply to "n2" goes to n2's configured address, which nobody listens on, rather than back to the impostor
+    let mut impostor = Client::connect(&addrs[0]);
+    impostor
+        .stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let read = json!({"src": "n2", "dest": "n1", "body": {"type": "read", "msg_id": 1}});
+    writeln!(impostor.stream, "{read}").unwrap();
+    let mut line = String::new();
+    assert!(impostor.reader.read_line(&mut line).is_err(), "the impostor got {line}");
+
+    let mut client = Client::connect(&addrs[0]);
+    assert_eq!(client.rpc("n1", json!({"type": "read"}))["value"], 0);
+}
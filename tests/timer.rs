@@ -0,0 +1,144 @@
+use dist_sys_challenge::{
+    message::{Error, MsgId},
+    messages,
+    node::NodeId,
+    options::Options,
+    outbox::Sender,
+    sim::Simulation,
+    timer::Schedule,
+    workloads::workload::{Body, Workload},
+};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+
+mod common;
+
+#[messages(response = Reply, incoming = Handle)]
+pub enum Request {
+    #[ok]
+    Once { after: u64 },
+    #[ok]
+    Every { period: u64 },
+    #[ok(once: u64, every: u64)]
+    Count,
+}
+
+#[derive(Clone, Debug)]
+enum Alarm {
+    Once,
+    Every,
+}
+
+/// Sets timers on request, with durations in milliseconds, and counts how often they fired
+struct Alarms {
+    tx: Sender<Body<Self>>,
+    once: u64,
+    every: u64,
+}
+
+impl Workload for Alarms {
+    type Request = Request;
+    type Response = Reply;
+    type Timer = Alarm;
+
+    fn new(_id: NodeId, _all_nodes: HashSet<NodeId>, tx: Sender<Body<Self>>, _options: &Options) -> Self {
+        Alarms { tx, once: 0, every: 0 }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: &Request,
+        _src: &NodeId,
+        reponse_factory: impl FnOnce(Reply) -> Body<Self>,
+    ) -> Result<(), Error> {
+        let (timer, response) = match request.incoming(reponse_factory) {
+            Handle::Once { after, reply } => {
+                let schedule = Schedule::Once(Duration::from_millis(*after));
+                (Some((Alarm::Once, schedule)), reply.with(OnceOk {}))
+            }
+            Handle::Every { period, reply } => {
+                let schedule = Schedule::Every(Duration::from_millis(*period));
+                (Some((Alarm::Every, schedule)), reply.with(EveryOk {}))
+            }
+            Handle::Count { reply } => {
+                let count = CountOk {
+                    once: self.once,
+                    every: self.every,
+                };
+                (None, reply.with(count))
+            }
+        };
+        if let Some((timer, schedule)) = timer {
+            self.tx.send(Body::Timer { timer, schedule }).expect("send failed");
+        }
+        self.tx.send(response).expect("send failed");
+        Ok(())
+    }
+
+    fn handle_response(&mut self, _response: &Reply, _in_reply_to: MsgId, _src: &NodeId) {}
+
+    fn handle_timer(&mut self, timer: Alarm) {
+        match timer {
+            Alarm::Once => self.once += 1,
+            Alarm::Every => self.every += 1,
+        }
+    }
+}
+
+fn count(sim: &mut Simulation<Alarms>) -> Value {
+    let reply = sim.rpc("c1", "n1", json!({"type": "count"}));
+    json!({"once": reply["once"], "every": reply["every"]})
+}
+
+#[test]
+fn one_shot_timers_fire_once() {
+    let mut sim = common::steady(1, 3);
+    sim.rpc("c1", "n1", json!({"type": "once", "after": 100}));
+
+    sim.run_for(Duration::from_millis(50));
+    assert_eq!(count(&mut sim)["once"], 0);
+    sim.run_for(Duration::from_millis(100));
+    assert_eq!(count(&mut sim)["once"], 1);
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(count(&mut sim)["once"], 1);
+}
+
+#[test]
+fn periodic_timers_fire_every_period() {
+    let mut sim = common::steady(1, 3);
+    sim.rpc("c1", "n1", json!({"type": "every", "period": 100}));
+
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(count(&mut sim), json!({"once": 0, "every": 10}));
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(count(&mut sim)["every"], 20);
+}
+
+#[test]
+fn periodic_timers_skip_the_firings_a_paused_node_missed() {
+    let mut sim = common::steady(1, 3);
+    sim.rpc("c1", "n1", json!({"type": "every", "period": 100}));
+    sim.run_for(Duration::from_millis(250));
+    assert_eq!(count(&mut sim)["every"], 2);
+
+    // Ten firings fall within the pause, and a single one makes up for all of them
+    sim.pause("n1", Duration::from_secs(1));
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(count(&mut sim)["every"], 3);
+
+    // The timer keeps to its original schedule afterwards
+    sim.run_for(Duration::from_secs(1));
+    assert_eq!(count(&mut sim)["every"], 13);
+}
+
+#[test]
+fn periodic_timers_fire_at_most_every_millisecond() {
+    let mut sim = common::steady(1, 3);
+    sim.rpc("c1", "n1", json!({"type": "every", "period": 0}));
+
+    // A zero period is raised to the minimum instead of firing without time passing
+    sim.run_for(Duration::from_millis(100));
+    let every = count(&mut sim)["every"].as_u64().unwrap();
+    assert!((100..=102).contains(&every), "fired {every} times");
+}
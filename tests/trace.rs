@@ -0,0 +1,74 @@
+use dist_sys_challenge::{cluster::Cluster, trace::TRACES_VAR, workloads::broadcast::BroadcastWorkload};
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Every span in an OTLP JSON file, along with the node that exported it
+fn spans(path: &std::path::Path) -> Vec<(String, Value)> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let mut spans = Vec::new();
+    for line in text.lines() {
+        let request: Value = serde_json::from_str(line).unwrap();
+        for resource in request["resourceSpans"].as_array().unwrap() {
+            let node = resource["resource"]["attributes"][0]["value"]["stringValue"]
+                .as_str()
+                .unwrap();
+            for scope in resource["scopeSpans"].as_array().unwrap() {
+                for span in scope["spans"].as_array().unwrap() {
+                    spans.push((node.to_string(), span.clone()));
+                }
+            }
+        }
+    }
+    spans
+}
+
+#[test]
+fn gossip_continues_the_trace_of_a_broadcast() {
+    let path = std::env::temp_dir().join(format!("traces-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    std::env::set_var(TRACES_VAR, &path);
+
+    let cluster = Cluster::start::<BroadcastWorkload>(3);
+    let mut c1 = cluster.client("c1");
+    let nodes = cluster.nodes().to_vec();
+    let topology: Value = nodes.iter().map(|node| (node.clone(), json!(nodes))).collect();
+    for node in &nodes {
+        c1.rpc(node, json!({"type": "topology", "topology": topology}));
+    }
+    c1.rpc("n1", json!({"type": "broadcast", "message": 7}));
+
+    // Gossip with the broadcast's trace reaches every node, all of which the fanout covers
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let (broadcast, hops) = loop {
+        let spans = spans(&path);
+        let broadcast = spans
+            .iter()
+            .find(|(_, span)| span["name"] == "broadcast")
+            .map(|(_, span)| span.clone());
+        if let Some(broadcast) = broadcast {
+            let hops: Vec<(String, Value)> = spans
+                .into_iter()
+                .filter(|(_, span)| span["parentSpanId"] == broadcast["spanId"])
+                .collect();
+            if hops.len() == nodes.len() {
+                break (broadcast, hops);
+            }
+        }
+        assert!(Instant::now() < deadline, "gossip spans missing");
+        thread::sleep(Duration::from_millis(50));
+    };
+    drop(cluster);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(broadcast.get("parentSpanId").is_none());
+    assert_eq!(broadcast["traceId"].as_str().unwrap().len(), 32);
+    for (node, hop) in &hops {
+        assert_eq!(hop["name"], "gossip", "{node}");
+        assert_eq!(hop["traceId"], broadcast["traceId"], "{node}");
+        assert_eq!(hop["attributes"][0]["value"]["stringValue"], "n1", "{node}");
+    }
+    let mut reached: Vec<&str> = hops.iter().map(|(node, _)| node.as_str()).collect();
+    reached.sort();
+    assert_eq!(reached, nodes);
+}
@@ -0,0 +1,72 @@
+use dist_sys_challenge::{
+    node::Node,
+    transport::{Channel, Streams},
+    workloads::echo::EchoWorkload,
+};
+use serde_json::{json, Value};
+use std::io::{self, Cursor, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const INIT: &str = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+const ECHO: &str = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"hi"}}"#;
+
+/// An output that can be inspected once the node is done with it
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn parse(line: &str) -> Value {
+    serde_json::from_str(line).expect("valid json")
+}
+
+#[test]
+fn streams_replay_a_recorded_input() {
+    let output = Shared::default();
+    let input = Cursor::new(format!("{INIT}\n{ECHO}\n"));
+    Node::<EchoWorkload>::init_with(Streams(input, output.clone()))
+        .expect("initialized")
+        .run();
+
+    let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let replies: Vec<_> = written.lines().map(parse).collect();
+    assert_eq!(replies.len(), 2, "{written}");
+    assert_eq!(replies[0]["body"]["type"], "init_ok");
+    assert_eq!(
+        replies[1]["body"],
+        json!({"type": "echo_ok", "echo": "hi", "in_reply_to": 2})
+    );
+}
+
+#[test]
+fn input_closing_before_init_starts_no_node() {
+    let input = Cursor::new(format!("{ECHO}\n"));
+    let node = Node::<EchoWorkload>::init_with(Streams(input, io::sink()));
+    assert!(node.is_none());
+}
+
+#[test]
+fn channels_carry_one_message_per_line() {
+    let (input_send, input) = mpsc::channel();
+    let (output, output_recv) = mpsc::channel::<String>();
+    thread::spawn(move || Node::<EchoWorkload>::init_with(Channel { input, output }).map(Node::run));
+
+    input_send.send(INIT.to_string()).unwrap();
+    input_send.send(ECHO.to_string()).unwrap();
+    let timeout = Duration::from_secs(5);
+    assert_eq!(
+        parse(&output_recv.recv_timeout(timeout).unwrap())["body"]["type"],
+        "init_ok"
+    );
+    assert_eq!(parse(&output_recv.recv_timeout(timeout).unwrap())["body"]["echo"], "hi");
+}